@@ -46,6 +46,9 @@ export interface PresetDefinition {
   cpu: CPUType;
 
   screen: Screen;
+
+  /** Explicit SVG file names for each screen, in screen index order */
+  svgs?: string[];
 }
 
 export interface PlatformSpecification {
@@ -53,6 +56,13 @@ export interface PlatformSpecification {
   portMap: PlatformPortMapping;
   metadata: Metadata;
   rom: ROMName;
+  /** Per game overrides for which layout elements are rendered */
+  elements?: ElementRules;
+}
+
+export interface ElementRules {
+  include?: string[];
+  exclude?: string[];
 }
 
 export interface ROMName {
@@ -60,6 +70,15 @@ export interface ROMName {
   melody: string | undefined;
   romOwner?: string;
   romHash: string;
+  /** Applied to the ROM after it's verified against `romHash` */
+  patch?: RomPatch;
+}
+
+export interface RomPatch {
+  /** An IPS or BPS patch, relative to the manifest */
+  file: string;
+  /** The SHA-1 of the patched ROM. Required unless `--allow-patched-rom-mismatch` is used */
+  patchedHash?: string;
 }
 
 export interface Metadata {
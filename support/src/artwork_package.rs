@@ -0,0 +1,209 @@
+use std::{fs, path::Path};
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    assets::{get_rom_assets, load_rom, AssetPaths},
+    encode_format::{append_rom, encode_without_rom, write_output, EncodeOptions, CONFIG_LENGTH},
+    manifest::{PlatformSpecification, ROMName},
+    render::RenderedData,
+};
+
+const PACKAGE_MAGIC: &[u8; 4] = b"GNWA";
+const PACKAGE_VERSION: u8 = 1;
+
+///
+/// A .gnw without its ROM, which can be shared freely. Everything needed to find and verify the user's ROM is stored
+/// alongside the payload, so the package can be combined without the manifest
+///
+pub struct ArtworkPackage {
+    /// The manifest key
+    pub platform_name: String,
+    pub rom: String,
+    pub rom_owner: Option<String>,
+    pub rom_hash: [u8; 20],
+    /// The config, images, and mask table, exactly as they appear in the final .gnw
+    pub payload: Vec<u8>,
+}
+
+impl ArtworkPackage {
+    ///
+    /// Encode the game without its ROM. The manifest's ROM SHA-1 is recorded in the provenance, as it will be once
+    /// combined with a matching ROM
+    ///
+    pub fn new(
+        platform_name: &str,
+        data: &RenderedData,
+        platform: &PlatformSpecification,
+        manifest_hash: &[u8; 20],
+        options: &EncodeOptions,
+    ) -> Result<Self, String> {
+        if platform.rom.patch.is_some() {
+            return Err(
+                "Artwork packages can't be made for patched ROMs. Distribute the patch instead"
+                    .to_string(),
+            );
+        }
+
+        let rom_hash = parse_hash(&platform.rom.rom_hash)?;
+
+        let payload = encode_without_rom(
+            platform_name,
+            data,
+            platform,
+            manifest_hash,
+            &rom_hash,
+            options,
+        )?;
+
+        Ok(ArtworkPackage {
+            platform_name: platform_name.to_string(),
+            rom: platform.rom.rom.clone(),
+            rom_owner: platform.rom.rom_owner.clone(),
+            rom_hash,
+            payload,
+        })
+    }
+
+    ///
+    /// Serialize as the magic, version, ROM SHA-1, length prefixed key, ROM name, and parent names (empty when there
+    /// is no parent), then the length prefixed payload. Lengths are little endian
+    ///
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = PACKAGE_MAGIC.to_vec();
+        bytes.push(PACKAGE_VERSION);
+        bytes.extend_from_slice(&self.rom_hash);
+
+        for value in [
+            self.platform_name.as_str(),
+            self.rom.as_str(),
+            self.rom_owner.as_deref().unwrap_or(""),
+        ] {
+            bytes.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(value.as_bytes());
+        }
+
+        bytes.extend_from_slice(&(self.payload.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.payload);
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = PackageReader { bytes, position: 0 };
+
+        if reader.take(PACKAGE_MAGIC.len())? != PACKAGE_MAGIC {
+            return Err("Not an artwork package".to_string());
+        }
+
+        let version = reader.take(1)?[0];
+
+        if version != PACKAGE_VERSION {
+            return Err(format!(
+                "Unsupported artwork package version {version}. Expected {PACKAGE_VERSION}"
+            ));
+        }
+
+        let rom_hash = reader.take(20)?.try_into().unwrap();
+        let platform_name = reader.string()?;
+        let rom = reader.string()?;
+        let rom_owner = reader.string()?;
+
+        let payload_length = u32::from_le_bytes(reader.take(4)?.try_into().unwrap()) as usize;
+        let payload = reader.take(payload_length)?.to_vec();
+
+        if payload.len() < CONFIG_LENGTH {
+            return Err("Artwork package payload is truncated".to_string());
+        }
+
+        Ok(ArtworkPackage {
+            platform_name,
+            rom,
+            rom_owner: if rom_owner.is_empty() {
+                None
+            } else {
+                Some(rom_owner)
+            },
+            rom_hash,
+            payload,
+        })
+    }
+
+    pub fn write(&self, output_path: &Path) -> Result<(), String> {
+        write_output(output_path, &self.to_bytes())
+    }
+}
+
+struct PackageReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> PackageReader<'a> {
+    #[allow(clippy::diverging_sub_expression)]
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        guard!(let Some(slice) = self.bytes.get(self.position..self.position + length) else {
+            return Err("Artwork package is truncated".to_string());
+        });
+
+        self.position += length;
+
+        Ok(slice)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let length = u16::from_le_bytes(self.take(2)?.try_into().unwrap()) as usize;
+
+        String::from_utf8(self.take(length)?.to_vec())
+            .map_err(|_| "Artwork package contains an invalid name".to_string())
+    }
+}
+
+fn parse_hash(hash: &str) -> Result<[u8; 20], String> {
+    hex::decode(hash.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("Invalid ROM SHA-1 \"{hash}\""))
+}
+
+///
+/// Find the package's ROM in the asset paths, verify its hash, and write the completed .gnw. The result is identical
+/// to encoding the game with the ROM directly
+///
+pub fn combine(
+    package_path: &Path,
+    paths: &AssetPaths,
+    temp_dir: &Path,
+    output_path: &Path,
+) -> Result<(), String> {
+    let file =
+        fs::read(package_path).map_err(|err| format!("Could not read {package_path:?}: {err}"))?;
+    let package = ArtworkPackage::from_bytes(&file)?;
+
+    let rom = ROMName {
+        rom: package.rom.clone(),
+        melody: None,
+        rom_owner: package.rom_owner.clone(),
+        rom_hash: hex::encode(package.rom_hash),
+        patch: None,
+    };
+
+    let asset_dir = temp_dir.join(&package.platform_name);
+
+    get_rom_assets(&package.platform_name, &rom.rom_owner, paths, &asset_dir)?;
+
+    // The package records the expected hash as the file's ROM hash, so a mismatched ROM can't be allowed
+    let rom_data = load_rom(&package.platform_name, &rom, paths, &asset_dir, false)?;
+
+    if Sha1::digest(&rom_data).as_slice() != package.rom_hash {
+        return Err(format!(
+            "ROM {} does not match the artwork package",
+            rom.rom
+        ));
+    }
+
+    let mut gnw = package.payload;
+    append_rom(&mut gnw, rom_data);
+
+    write_output(output_path, &gnw)
+}
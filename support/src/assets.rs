@@ -1,36 +1,202 @@
-use std::{fs::File, path::Path};
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
 
 use colored::Colorize;
+use sha1::{Digest, Sha1};
 use zip::ZipArchive;
 
+use crate::manifest::ROMName;
+
+///
+/// The directories searched, in order, for ROM and artwork sets
+///
+#[derive(Debug, Default)]
+pub struct AssetPaths {
+    pub rom_paths: Vec<PathBuf>,
+    pub artwork_paths: Vec<PathBuf>,
+}
+
+///
+/// A set is either a zip, or an unzipped directory of the same name
+///
+#[derive(Debug)]
+pub enum AssetSource {
+    Zip(PathBuf),
+    Directory(PathBuf),
+}
+
+impl AssetPaths {
+    ///
+    /// Collect the search paths. Explicit paths are searched first, then the `rompath` and `artpath` entries of the
+    /// `mame.ini`, then the `roms` and `artwork` directories of the MAME directory
+    ///
+    #[allow(clippy::diverging_sub_expression)]
+    pub fn new(
+        mame_path: Option<&Path>,
+        mame_ini: Option<&Path>,
+        rom_paths: &[PathBuf],
+        artwork_paths: &[PathBuf],
+    ) -> Result<Self, String> {
+        let mut paths = AssetPaths {
+            rom_paths: rom_paths.to_vec(),
+            artwork_paths: artwork_paths.to_vec(),
+        };
+
+        if let Some(mame_ini) = mame_ini {
+            let ini = fs::read_to_string(mame_ini)
+                .map_err(|err| format!("Could not read {mame_ini:?}: {err}"))?;
+            // Relative paths in the ini are relative to MAME's directory, which holds the ini
+            let base = mame_ini.parent().unwrap_or(Path::new(""));
+
+            for line in ini.lines() {
+                let line = line.trim();
+
+                if line.starts_with('#') {
+                    continue;
+                }
+
+                guard!(let Some((key, value)) = line.split_once(char::is_whitespace) else {
+                    continue;
+                });
+
+                let list = match key {
+                    "rompath" => &mut paths.rom_paths,
+                    "artpath" => &mut paths.artwork_paths,
+                    _ => continue,
+                };
+
+                list.extend(
+                    value
+                        .trim()
+                        .trim_matches('"')
+                        .split(';')
+                        .filter(|path| !path.is_empty())
+                        .map(|path| base.join(path)),
+                );
+            }
+        }
+
+        if let Some(mame_path) = mame_path {
+            paths.rom_paths.push(mame_path.join("roms"));
+            paths.artwork_paths.push(mame_path.join("artwork"));
+        }
+
+        Ok(paths)
+    }
+
+    fn find_set(paths: &[PathBuf], name: &str) -> Option<AssetSource> {
+        for path in paths {
+            let zip_path = path.join(format!("{name}.zip"));
+
+            if zip_path.is_file() {
+                return Some(AssetSource::Zip(zip_path));
+            }
+
+            let directory_path = path.join(name);
+
+            if directory_path.is_dir() {
+                return Some(AssetSource::Directory(directory_path));
+            }
+        }
+
+        None
+    }
+
+    pub fn find_rom_set(&self, name: &str) -> Option<AssetSource> {
+        AssetPaths::find_set(&self.rom_paths, name)
+    }
+
+    pub fn find_artwork_set(&self, name: &str) -> Option<AssetSource> {
+        AssetPaths::find_set(&self.artwork_paths, name)
+    }
+}
+
+impl AssetSource {
+    pub fn path(&self) -> &Path {
+        match self {
+            AssetSource::Zip(path) | AssetSource::Directory(path) => path,
+        }
+    }
+
+    ///
+    /// The names of the files in the set, without extracting it
+    ///
+    pub fn file_names(&self) -> Vec<String> {
+        match self {
+            AssetSource::Zip(zip_path) => File::open(zip_path)
+                .ok()
+                .and_then(|file| ZipArchive::new(file).ok())
+                .map(|archive| archive.file_names().map(|name| name.to_string()).collect())
+                .unwrap_or_default(),
+            AssetSource::Directory(directory_path) => fs::read_dir(directory_path)
+                .map(|entries| {
+                    entries
+                        .flatten()
+                        .map(|entry| entry.file_name().to_string_lossy().to_string())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    fn read_file(&self, name: &str) -> Option<Vec<u8>> {
+        match self {
+            AssetSource::Zip(zip_path) => {
+                let mut archive = ZipArchive::new(File::open(zip_path).ok()?).ok()?;
+                let mut entry = archive.by_name(name).ok()?;
+
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).ok()?;
+
+                Some(data)
+            }
+            AssetSource::Directory(directory_path) => fs::read(directory_path.join(name)).ok(),
+        }
+    }
+}
+
 ///
 /// Extract artwork and ROM assets
 ///
 pub fn get_assets(
     platform_name: &str,
     owning_rom_name: &Option<String>,
-    mame_path: &Path,
+    paths: &AssetPaths,
     temp_dir: &Path,
 ) -> Result<(), String> {
-    let artwork_path = mame_path
-        .join("artwork/foo")
-        .with_file_name(platform_name)
-        .with_extension("zip");
+    extract_set(
+        paths.find_artwork_set(platform_name),
+        platform_name,
+        &paths.artwork_paths,
+        temp_dir,
+        "artwork",
+    )?;
 
-    let roms_path = mame_path
-        .join("roms/foo")
-        .with_file_name(platform_name)
-        .with_extension("zip");
+    get_rom_assets(platform_name, owning_rom_name, paths, temp_dir)
+}
 
+///
+/// Extract the game's ROM set, and its parent's if it has one
+///
+pub fn get_rom_assets(
+    platform_name: &str,
+    owning_rom_name: &Option<String>,
+    paths: &AssetPaths,
+    temp_dir: &Path,
+) -> Result<(), String> {
     let mut has_parent = false;
 
     if let Some(owning_rom_name) = owning_rom_name {
-        let owning_roms_path = mame_path
-            .join("roms/foo")
-            .with_file_name(owning_rom_name)
-            .with_extension("zip");
-
-        if let Err(message) = extract_path(&owning_roms_path, &temp_dir, "parent ROM") {
+        if let Err(message) = extract_set(
+            paths.find_rom_set(owning_rom_name),
+            owning_rom_name,
+            &paths.rom_paths,
+            temp_dir,
+            "parent ROM",
+        ) {
             return Err(format!(
                 "Device is dependent on parent ROM {}\n{message}",
                 owning_rom_name.cyan()
@@ -40,8 +206,13 @@ pub fn get_assets(
         has_parent = true;
     }
 
-    extract_path(&artwork_path, &temp_dir, "artwork")?;
-    match extract_path(&roms_path, &temp_dir, "ROM") {
+    match extract_set(
+        paths.find_rom_set(platform_name),
+        platform_name,
+        &paths.rom_paths,
+        temp_dir,
+        "ROM",
+    ) {
         Ok(_) => Ok(()),
         Err(err) => {
             // If we found a parent, we don't require a ROM for this title
@@ -54,15 +225,211 @@ pub fn get_assets(
     }
 }
 
-fn extract_path(file_path: &Path, outdir: &Path, data_type: &str) -> Result<(), String> {
-    guard!(let Ok(zip_file) = File::open(file_path) else {
-        let name = if let Some(name) = file_path.file_name() {
-            format!(" ({name:?})")
-        } else {
-            "".to_string()
-        };
+///
+/// Load the game's ROM, verifying it against the manifest's SHA-1. If the named file doesn't match, the game's ROM
+/// set and its parent's are searched for a file with the expected hash. If `allow_mismatch` is set, a named file
+/// with the wrong hash is used with a warning, rather than failing
+///
+#[allow(clippy::diverging_sub_expression)]
+pub fn load_rom(
+    platform_name: &str,
+    rom: &ROMName,
+    paths: &AssetPaths,
+    asset_dir: &Path,
+    allow_mismatch: bool,
+) -> Result<Vec<u8>, String> {
+    // TODO: Add melody ROM
+    let rom_path = asset_dir.join(&rom.rom);
+    let expected_hash = rom.rom_hash.to_lowercase();
+
+    let named_rom = fs::read(&rom_path).ok().map(|data| {
+        let hash = hex::encode(Sha1::digest(&data));
+
+        (data, hash)
+    });
 
-        return Err(format!("Could not open expected {data_type} file{name} at {file_path:?}"));
+    if let Some((data, hash)) = &named_rom {
+        if hash == &expected_hash {
+            return Ok(data.clone());
+        }
+    }
+
+    let mut set_names = vec![platform_name];
+
+    if let Some(owner) = &rom.rom_owner {
+        set_names.push(owner);
+    }
+
+    for set_name in set_names {
+        guard!(let Some(source) = paths.find_rom_set(set_name) else {
+            continue;
+        });
+
+        if let Some((entry_name, data)) = find_entry_by_hash(&source, &expected_hash) {
+            println!(
+                "{}",
+                format!(
+                    "ROM {} matched by SHA-1 as {entry_name} in {source}",
+                    rom.rom
+                )
+                .yellow()
+            );
+
+            return Ok(data);
+        }
+    }
+
+    guard!(let Some((data, actual_hash)) = named_rom else {
+        return Err(format!(
+            "Could not find ROM {rom_path:?}, and no file matched the expected SHA-1 {expected_hash}"
+        ));
+    });
+
+    let message = format!(
+        "ROM {} SHA-1 mismatch\nExpected: {expected_hash}\nActual:   {actual_hash}",
+        rom.rom
+    );
+
+    if allow_mismatch {
+        println!("{}", format!("{message}\nUsing mismatched ROM").yellow());
+
+        Ok(data)
+    } else {
+        Err(format!(
+            "{message}\nThis may be a bad dump or a different revision. Use `--allow-rom-mismatch` to use it anyway"
+        ))
+    }
+}
+
+pub enum RomStatus {
+    Verified,
+    /// The named ROM was found, but with this SHA-1
+    Mismatch(String),
+    Missing,
+}
+
+///
+/// Check for a ROM matching the manifest's SHA-1 in the game's and parent's sets, without extracting them
+///
+pub fn check_rom(platform_name: &str, rom: &ROMName, paths: &AssetPaths) -> RomStatus {
+    let expected_hash = rom.rom_hash.to_lowercase();
+
+    let sources = [Some(platform_name), rom.rom_owner.as_deref()]
+        .into_iter()
+        .flatten()
+        .filter_map(|name| paths.find_rom_set(name))
+        .collect::<Vec<AssetSource>>();
+
+    if sources
+        .iter()
+        .any(|source| find_entry_by_hash(source, &expected_hash).is_some())
+    {
+        return RomStatus::Verified;
+    }
+
+    match sources.iter().find_map(|source| source.read_file(&rom.rom)) {
+        Some(data) => RomStatus::Mismatch(hex::encode(Sha1::digest(data))),
+        None => RomStatus::Missing,
+    }
+}
+
+#[allow(clippy::diverging_sub_expression)]
+fn find_entry_by_hash(source: &AssetSource, target_hash: &str) -> Option<(String, Vec<u8>)> {
+    let matches = |data: &[u8]| hex::encode(Sha1::digest(data)) == target_hash;
+
+    match source {
+        AssetSource::Zip(zip_path) => {
+            let zip_file = File::open(zip_path).ok()?;
+            let mut archive = ZipArchive::new(zip_file).ok()?;
+
+            for i in 0..archive.len() {
+                guard!(let Ok(mut entry) = archive.by_index(i) else {
+                    continue;
+                });
+
+                if !entry.is_file() {
+                    continue;
+                }
+
+                let mut data = Vec::new();
+
+                if entry.read_to_end(&mut data).is_err() {
+                    continue;
+                }
+
+                if matches(&data) {
+                    return Some((entry.name().to_string(), data));
+                }
+            }
+
+            None
+        }
+        AssetSource::Directory(directory_path) => {
+            for entry in fs::read_dir(directory_path).ok()?.flatten() {
+                guard!(let Ok(data) = fs::read(entry.path()) else {
+                    continue;
+                });
+
+                if matches(&data) {
+                    return Some((entry.file_name().to_string_lossy().to_string(), data));
+                }
+            }
+
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for AssetSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssetSource::Zip(path) | AssetSource::Directory(path) => write!(f, "{path:?}"),
+        }
+    }
+}
+
+#[allow(clippy::diverging_sub_expression)]
+fn extract_set(
+    source: Option<AssetSource>,
+    name: &str,
+    search_paths: &[PathBuf],
+    outdir: &Path,
+    data_type: &str,
+) -> Result<(), String> {
+    guard!(let Some(source) = source else {
+        return Err(format!(
+            "Could not find expected {data_type} file ({name}.zip or {name} directory) in {search_paths:?}"
+        ));
+    });
+
+    match source {
+        AssetSource::Zip(file_path) => extract_path(&file_path, outdir),
+        AssetSource::Directory(directory_path) => copy_directory(&directory_path, outdir),
+    }
+}
+
+fn copy_directory(directory_path: &Path, outdir: &Path) -> Result<(), String> {
+    let copy = || -> std::io::Result<()> {
+        fs::create_dir_all(outdir)?;
+
+        for entry in fs::read_dir(directory_path)? {
+            let entry = entry?;
+
+            if entry.file_type()?.is_file() {
+                fs::copy(entry.path(), outdir.join(entry.file_name()))?;
+            }
+        }
+
+        Ok(())
+    };
+
+    copy().map_err(|err| format!("Could not copy set directory {directory_path:?}: {err}"))
+}
+
+#[allow(clippy::diverging_sub_expression)]
+fn extract_path(file_path: &Path, outdir: &Path) -> Result<(), String> {
+    guard!(let Ok(zip_file) = File::open(file_path) else {
+        return Err(format!("Could not open zip at {file_path:?}"));
     });
 
     guard!(let Ok(mut archive) = ZipArchive::new(zip_file) else {
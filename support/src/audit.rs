@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+
+use colored::{ColoredString, Colorize};
+
+use crate::{
+    assets::{check_rom, AssetPaths, AssetSource, RomStatus},
+    manifest::PlatformSpecification,
+    render::{screen_filename, screen_suffixes},
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Check {
+    Present,
+    Missing,
+    /// Not needed for this game
+    Unused,
+}
+
+impl Check {
+    fn from_bool(present: bool) -> Self {
+        if present {
+            Check::Present
+        } else {
+            Check::Missing
+        }
+    }
+
+    fn format(&self, width: usize) -> ColoredString {
+        match self {
+            Check::Present => format!("{:width$}", "yes").green(),
+            Check::Missing => format!("{:width$}", "no").red(),
+            Check::Unused => format!("{:width$}", "-").normal(),
+        }
+    }
+}
+
+struct AuditEntry {
+    name: String,
+    company: String,
+    cpu: String,
+    artwork: Check,
+    rom: Check,
+    parent: Check,
+    /// `default.lay`, or any `.lay` file if there is no default
+    layout: Check,
+    default_layout: bool,
+    svgs: Check,
+    missing_svgs: Vec<String>,
+    rom_status: RomStatus,
+}
+
+impl AuditEntry {
+    fn is_ready(&self) -> bool {
+        [self.artwork, self.rom, self.parent, self.layout, self.svgs]
+            .iter()
+            .all(|check| *check != Check::Missing)
+            && matches!(self.rom_status, RomStatus::Verified)
+    }
+}
+
+fn audit_platform(name: &str, platform: &PlatformSpecification, paths: &AssetPaths) -> AuditEntry {
+    let artwork_set = paths.find_artwork_set(name);
+    let rom_set = paths.find_rom_set(name);
+    let parent_set = platform
+        .rom
+        .rom_owner
+        .as_ref()
+        .and_then(|owner| paths.find_rom_set(owner));
+
+    let list_files =
+        |set: &Option<AssetSource>| set.as_ref().map(|set| set.file_names()).unwrap_or_default();
+
+    let artwork_files = list_files(&artwork_set);
+
+    // SVGs are extracted alongside the artwork, so they can come from any of the sets
+    let mut all_files = artwork_files.clone();
+    all_files.extend(list_files(&rom_set));
+    all_files.extend(list_files(&parent_set));
+
+    let has_file = |file_name: &str| {
+        all_files
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(file_name))
+    };
+
+    let default_layout = artwork_files
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case("default.lay"));
+    let any_layout = artwork_files
+        .iter()
+        .any(|name| name.to_lowercase().ends_with(".lay"));
+
+    let screen_count = screen_suffixes(&platform.device.screen).len();
+
+    let missing_svgs = (0..screen_count)
+        .filter(|index| {
+            let file_name = screen_filename(*index, name, &platform.device);
+
+            // Matches the parent named fallback used when rendering
+            let parent_file_name = if platform.device.svgs.is_none() {
+                platform
+                    .rom
+                    .rom_owner
+                    .as_ref()
+                    .map(|owner| screen_filename(*index, owner, &platform.device))
+            } else {
+                None
+            };
+
+            !has_file(&file_name) && !parent_file_name.is_some_and(|name| has_file(&name))
+        })
+        .map(|index| screen_filename(index, name, &platform.device))
+        .collect::<Vec<String>>();
+
+    AuditEntry {
+        name: name.to_string(),
+        company: platform.metadata.company.clone(),
+        cpu: format!("{:?}", platform.device.cpu),
+        artwork: Check::from_bool(artwork_set.is_some()),
+        // A game with a parent doesn't need its own ROM set
+        rom: if rom_set.is_none() && parent_set.is_some() {
+            Check::Unused
+        } else {
+            Check::from_bool(rom_set.is_some())
+        },
+        parent: if platform.rom.rom_owner.is_some() {
+            Check::from_bool(parent_set.is_some())
+        } else {
+            Check::Unused
+        },
+        layout: Check::from_bool(any_layout),
+        default_layout,
+        svgs: Check::from_bool(missing_svgs.is_empty()),
+        missing_svgs,
+        rom_status: check_rom(name, &platform.rom, paths),
+    }
+}
+
+///
+/// Check every platform for the assets needed to generate it, printing a matrix of the results and a summary per
+/// company and CPU
+///
+pub fn audit(platforms: &[(String, &PlatformSpecification)], paths: &AssetPaths) {
+    let entries = platforms
+        .iter()
+        .map(|(name, platform)| audit_platform(name, platform, paths))
+        .collect::<Vec<AuditEntry>>();
+
+    let name_width = entries
+        .iter()
+        .map(|entry| entry.name.len())
+        .max()
+        .unwrap_or(0)
+        .max("Game".len());
+
+    println!(
+        "{:name_width$}  Artwork  ROM  Parent  Layout  SVG  Hash      Notes",
+        "Game"
+    );
+
+    for entry in &entries {
+        let hash = match &entry.rom_status {
+            RomStatus::Verified => format!("{:9}", "ok").green(),
+            RomStatus::Mismatch(_) => format!("{:9}", "mismatch").red(),
+            RomStatus::Missing => format!("{:9}", "missing").red(),
+        };
+
+        let mut notes = vec![];
+
+        if entry.layout == Check::Present && !entry.default_layout {
+            notes.push("no default.lay".to_string());
+        }
+
+        if !entry.missing_svgs.is_empty() {
+            notes.push(format!("missing {}", entry.missing_svgs.join(", ")));
+        }
+
+        if let RomStatus::Mismatch(hash) = &entry.rom_status {
+            notes.push(format!("ROM SHA-1 {hash}"));
+        }
+
+        println!(
+            "{:name_width$}  {}  {}  {}  {}  {}  {} {}",
+            entry.name,
+            entry.artwork.format(7),
+            entry.rom.format(3),
+            entry.parent.format(6),
+            entry.layout.format(6),
+            entry.svgs.format(3),
+            hash,
+            notes.join("; ")
+        );
+    }
+
+    let print_summary = |title: &str, key: fn(&AuditEntry) -> &String| {
+        // (ready, total)
+        let mut groups: BTreeMap<&String, (usize, usize)> = BTreeMap::new();
+
+        for entry in &entries {
+            let group = groups.entry(key(entry)).or_default();
+
+            if entry.is_ready() {
+                group.0 += 1;
+            }
+
+            group.1 += 1;
+        }
+
+        println!("\n{title}:");
+
+        for (name, (ready, total)) in groups {
+            println!("  {name}: {ready} of {total} ready");
+        }
+    };
+
+    print_summary("By company", |entry| &entry.company);
+    print_summary("By CPU", |entry| &entry.cpu);
+
+    let ready_count = entries.iter().filter(|entry| entry.is_ready()).count();
+
+    println!("-------------------------");
+    println!(
+        "Total: {}, Ready: {ready_count}, Incomplete: {}",
+        entries.len(),
+        entries.len() - ready_count
+    );
+}
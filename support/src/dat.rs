@@ -0,0 +1,226 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use colored::Colorize;
+use serde::Deserialize;
+
+use crate::manifest::PlatformSpecification;
+
+///
+/// A ROM listing, either a MAME `-listxml` output (`<mame>` of `<machine>`s) or a Logiqx DAT (`<datafile>` of
+/// `<game>`s). Parent/clone relationships are taken from `romof`, which is the set merged ROMs are stored in, falling
+/// back to `cloneof`
+///
+#[derive(Debug, Deserialize)]
+struct Datafile {
+    #[serde(default)]
+    machine: Vec<DatMachine>,
+    #[serde(default)]
+    game: Vec<DatMachine>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DatMachine {
+    name: String,
+    cloneof: Option<String>,
+    romof: Option<String>,
+    #[serde(default)]
+    rom: Vec<DatRom>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DatRom {
+    name: String,
+    sha1: Option<String>,
+    /// Only present in `-listxml` output
+    region: Option<String>,
+    /// Set when the ROM is shared with, and stored in, the parent set
+    merge: Option<String>,
+    status: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DatRomEntry {
+    pub name: String,
+    pub sha1: String,
+}
+
+///
+/// The ROMs of a single game, as resolved from a DAT
+///
+#[derive(Clone, Debug)]
+pub struct DatGame {
+    pub rom: DatRomEntry,
+    pub melody: Option<DatRomEntry>,
+    /// The parent set, if any of the ROMs are merged into it
+    pub rom_owner: Option<String>,
+}
+
+#[derive(PartialEq)]
+enum RomKind {
+    Program,
+    Melody,
+    Other,
+}
+
+impl DatRom {
+    fn kind(&self) -> RomKind {
+        let name = self.name.to_lowercase();
+
+        match &self.region {
+            // MAME stores the melody in the `maincpu:melody` region
+            Some(region) if region.ends_with("melody") => RomKind::Melody,
+            Some(region) if region == "maincpu" => RomKind::Program,
+            Some(_) => RomKind::Other,
+            // Logiqx DATs don't have regions, so fall back to MAME's file naming
+            None if name.ends_with(".melody") => RomKind::Melody,
+            None if name.ends_with(".svg") => RomKind::Other,
+            None => RomKind::Program,
+        }
+    }
+
+    fn entry(&self) -> Option<DatRomEntry> {
+        if self.status.as_deref() == Some("nodump") {
+            return None;
+        }
+
+        self.sha1.as_ref().map(|sha1| DatRomEntry {
+            name: self.name.clone(),
+            sha1: sha1.to_lowercase(),
+        })
+    }
+}
+
+///
+/// Parse a MAME `-listxml` output or Logiqx DAT, keyed by set name. Sets without a program ROM with a SHA-1 are
+/// skipped
+///
+#[allow(clippy::diverging_sub_expression)]
+pub fn parse_dat(path: &Path) -> Result<HashMap<String, DatGame>, String> {
+    let file =
+        fs::read_to_string(path).map_err(|err| format!("Could not read DAT {path:?}: {err}"))?;
+
+    let datafile: Datafile = serde_xml_rs::from_str(&file)
+        .map_err(|err| format!("Could not parse DAT {path:?}: {err}"))?;
+
+    let mut games = HashMap::new();
+
+    for machine in datafile.machine.into_iter().chain(datafile.game) {
+        let program = machine
+            .rom
+            .iter()
+            .find(|rom| rom.kind() == RomKind::Program);
+
+        guard!(let Some(rom) = program.and_then(|rom| rom.entry()) else {
+            continue;
+        });
+
+        let melody = machine
+            .rom
+            .iter()
+            .find(|rom| rom.kind() == RomKind::Melody)
+            .and_then(|rom| rom.entry());
+
+        // Merged ROMs (including the screen SVGs) are only stored in the parent set
+        let rom_owner = if machine.rom.iter().any(|rom| rom.merge.is_some()) {
+            machine.romof.clone().or(machine.cloneof.clone())
+        } else {
+            None
+        };
+
+        games.insert(
+            machine.name,
+            DatGame {
+                rom,
+                melody,
+                rom_owner,
+            },
+        );
+    }
+
+    Ok(games)
+}
+
+///
+/// Replace the manifest's ROM names, hashes, owners, and melody ROMs with the ones from the DAT, printing any
+/// disagreements. Returns the number of entries that disagreed
+///
+pub fn apply_dat(
+    manifest: &mut HashMap<String, PlatformSpecification>,
+    dat: &HashMap<String, DatGame>,
+) -> usize {
+    let mut names = manifest.keys().cloned().collect::<Vec<String>>();
+    names.sort();
+
+    let mut missing = vec![];
+    let mut mismatch_count = 0;
+
+    for name in names {
+        let platform = manifest.get_mut(&name).unwrap();
+
+        guard!(let Some(game) = dat.get(&name) else {
+            missing.push(name);
+            continue;
+        });
+
+        let rom = &mut platform.rom;
+        let mut differences = vec![];
+
+        let mut compare =
+            |field: &str, manifest_value: Option<&String>, dat_value: Option<&String>| {
+                if manifest_value != dat_value {
+                    let format_value = |value: Option<&String>| {
+                        value.map_or("none".to_string(), |value| value.clone())
+                    };
+
+                    differences.push(format!(
+                        "  {field}: manifest {}, DAT {}",
+                        format_value(manifest_value),
+                        format_value(dat_value)
+                    ));
+                }
+            };
+
+        let manifest_hash = rom.rom_hash.to_lowercase();
+
+        compare("ROM", Some(&rom.rom), Some(&game.rom.name));
+        compare("SHA-1", Some(&manifest_hash), Some(&game.rom.sha1));
+        compare(
+            "Melody",
+            rom.melody.as_ref(),
+            game.melody.as_ref().map(|melody| &melody.name),
+        );
+        compare("Owner", rom.rom_owner.as_ref(), game.rom_owner.as_ref());
+
+        rom.rom = game.rom.name.clone();
+        rom.rom_hash = game.rom.sha1.clone();
+        rom.melody = game.melody.as_ref().map(|melody| melody.name.clone());
+        rom.rom_owner = game.rom_owner.clone();
+
+        if !differences.is_empty() {
+            mismatch_count += 1;
+
+            println!(
+                "{}",
+                format!("Manifest entry {name} disagrees with the DAT:").yellow()
+            );
+
+            for difference in differences {
+                println!("{difference}");
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        println!(
+            "{}",
+            format!(
+                "{} manifest entries not found in the DAT, using manifest ROMs: {}",
+                missing.len(),
+                missing.join(", ")
+            )
+            .yellow()
+        );
+    }
+
+    mismatch_count
+}
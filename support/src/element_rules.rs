@@ -0,0 +1,126 @@
+use regex::{Regex, RegexBuilder};
+
+use crate::manifest::ElementRuleConfig;
+
+/// Elements that are skipped unless explicitly included. These are usually device overlays that obscure the game
+const DEFAULT_EXCLUDES: [&str; 5] = ["dust", "bubbles", "unit", "backdrop", "gradient*"];
+
+///
+/// A single element name pattern. Patterns wrapped in slashes (`/^fix\d+$/`) are regexes, everything else is a
+/// glob supporting `*` and `?`. All matching is case insensitive
+///
+#[derive(Clone, Debug)]
+pub struct ElementRule {
+    pattern: String,
+    regex: Regex,
+}
+
+impl ElementRule {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        let pattern = pattern.trim();
+
+        let expression = if pattern.len() > 1 && pattern.starts_with('/') && pattern.ends_with('/')
+        {
+            pattern[1..pattern.len() - 1].to_string()
+        } else {
+            let escaped = regex::escape(pattern)
+                .replace("\\*", ".*")
+                .replace("\\?", ".");
+
+            format!("^{escaped}$")
+        };
+
+        match RegexBuilder::new(&expression)
+            .case_insensitive(true)
+            .build()
+        {
+            Ok(regex) => Ok(ElementRule {
+                pattern: pattern.to_string(),
+                regex,
+            }),
+            Err(err) => Err(format!("Invalid element rule \"{pattern}\": {err}")),
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.regex.is_match(name)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct ElementRuleSet {
+    source: String,
+    include: Vec<ElementRule>,
+    exclude: Vec<ElementRule>,
+}
+
+impl ElementRuleSet {
+    fn new(source: &str, config: &ElementRuleConfig) -> Result<Self, String> {
+        let build = |patterns: &Vec<String>| {
+            patterns
+                .iter()
+                .map(|p| ElementRule::new(p))
+                .collect::<Result<Vec<ElementRule>, String>>()
+        };
+
+        Ok(ElementRuleSet {
+            source: source.to_string(),
+            include: build(&config.include)?,
+            exclude: build(&config.exclude)?,
+        })
+    }
+}
+
+///
+/// Layered include/exclude rules deciding which layout elements are rendered. The most specific layer (per game,
+/// then global, then the defaults) that matches an element decides whether it is kept, and includes win over
+/// excludes within a layer
+///
+#[derive(Clone, Debug)]
+pub struct ElementRules {
+    layers: Vec<ElementRuleSet>,
+}
+
+impl ElementRules {
+    pub fn new(global: &ElementRuleConfig, use_defaults: bool) -> Result<Self, String> {
+        let mut layers = vec![ElementRuleSet::new("global", global)?];
+
+        if use_defaults {
+            layers.push(ElementRuleSet::new(
+                "default",
+                &ElementRuleConfig {
+                    include: vec![],
+                    exclude: DEFAULT_EXCLUDES.iter().map(|p| p.to_string()).collect(),
+                },
+            )?);
+        }
+
+        Ok(ElementRules { layers })
+    }
+
+    /// Adds the rules specified by a game's manifest entry, which take priority over all others
+    pub fn with_game(&self, game: Option<&ElementRuleConfig>) -> Result<Self, String> {
+        let mut rules = self.clone();
+
+        if let Some(game) = game {
+            rules.layers.insert(0, ElementRuleSet::new("game", game)?);
+        }
+
+        Ok(rules)
+    }
+
+    /// Returns a description of the rule that excludes the element, if any
+    pub fn excluded_by(&self, name: &str) -> Option<String> {
+        for layer in &self.layers {
+            if layer.include.iter().any(|r| r.matches(name)) {
+                return None;
+            }
+
+            if let Some(rule) = layer.exclude.iter().find(|r| r.matches(name)) {
+                return Some(format!("{} rule \"{}\"", layer.source, rule.pattern));
+            }
+        }
+
+        None
+    }
+}
@@ -1,7 +1,7 @@
 use std::{
-    fs::{self, File},
-    io::{self, Read, Seek},
-    path::{Path, PathBuf},
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
 };
 
 use bitvec::{
@@ -9,27 +9,119 @@ use bitvec::{
     prelude::{bitvec, Lsb0},
 };
 
+use colored::Colorize;
+use resvg::tiny_skia::Pixmap;
 use sha1::{Digest, Sha1};
 
 use crate::{
     manifest::{Action, CPUType, NamedAction, PlatformSpecification, Port, Screen},
+    mask_budget::{
+        count_runs, drop_islands, merge_gaps, MaskOverflowOptions, MaskOverflowStrategy,
+    },
+    render::RenderedData,
+    svg_manage::format_segment_id,
     HEIGHT, WIDTH,
 };
 
+pub struct EncodeOptions {
+    /// Write the IDs of pixels shared by multiple segments as secondary mask runs
+    pub shared_segments: bool,
+    pub overflow: MaskOverflowOptions,
+}
+
+pub const CONFIG_LENGTH: usize = 0x100;
+/// Interleaved background and mask images, without alpha
+pub const IMAGE_LENGTH: usize = WIDTH * HEIGHT * 3 * 2;
+
+pub const MASK_FLAGS: usize = 0x30;
+pub const MASK_FLAG_SECONDARY_RUNS: u8 = 0x1;
+pub const MASK_FLAG_EXTENDED_TABLE: u8 = 0x2;
+pub const EXTENDED_MASK_ENTRIES: usize = 0x35;
+
+pub const PROVENANCE_VERSION: u8 = 1;
+pub const PROVENANCE: usize = 0x3F;
+/// SHA-1 of all data after the config
+pub const PROVENANCE_PAYLOAD_HASH: usize = 0x40;
+/// SHA-1 of the embedded ROM
+pub const PROVENANCE_ROM_HASH: usize = 0x54;
+/// SHA-1 of the manifest entry used to generate the file
+pub const PROVENANCE_MANIFEST_HASH: usize = 0x68;
+/// Generator semver, as major, minor, and patch bytes
+pub const PROVENANCE_GENERATOR_VERSION: usize = 0x7C;
+pub const GIT_SHA: usize = 0xF9;
+
+struct Provenance {
+    rom_hash: [u8; 20],
+    manifest_hash: [u8; 20],
+}
+
+pub fn generator_version() -> [u8; 3] {
+    let parse = |value: &str| value.parse::<u8>().unwrap_or(0);
+
+    [
+        parse(env!("CARGO_PKG_VERSION_MAJOR")),
+        parse(env!("CARGO_PKG_VERSION_MINOR")),
+        parse(env!("CARGO_PKG_VERSION_PATCH")),
+    ]
+}
+
 pub fn encode(
-    background_bytes: &[u8],
-    mask_bytes: &[u8],
-    pixels_to_mask_id: &[Option<u16>],
+    platform_name: &str,
+    data: &RenderedData,
     platform: &PlatformSpecification,
-    asset_dir: &Path,
-    output_dir: &Path,
-) -> Result<PathBuf, String> {
+    manifest_hash: &[u8; 20],
+    rom_data: Vec<u8>,
+    output_path: &Path,
+    options: &EncodeOptions,
+) -> Result<(), String> {
+    let rom_hash = Sha1::digest(&rom_data).into();
+
+    let mut file = encode_without_rom(
+        platform_name,
+        data,
+        platform,
+        manifest_hash,
+        &rom_hash,
+        options,
+    )?;
+
+    append_rom(&mut file, rom_data);
+
+    write_output(output_path, &file)
+}
+
+///
+/// Build everything in the file up to the ROM: the config, images, and mask table. The payload hash is left empty, and
+/// is written by `append_rom`
+///
+pub fn encode_without_rom(
+    platform_name: &str,
+    data: &RenderedData,
+    platform: &PlatformSpecification,
+    manifest_hash: &[u8; 20],
+    rom_hash: &[u8; 20],
+    options: &EncodeOptions,
+) -> Result<Vec<u8>, String> {
+    let provenance = Provenance {
+        rom_hash: *rom_hash,
+        manifest_hash: *manifest_hash,
+    };
+
+    // In order of preference. Labels are dropped first if the mask runs leave too little room
+    let metadata = [
+        build_metadata(platform_name, platform, true),
+        build_metadata(platform_name, platform, false),
+    ];
+
+    // Build mask config. The config records the layout of the mask table
+    let mut mask_table = build_mask(data, &metadata, options)?;
+
     // Build config
-    let mut config = build_config(platform)?;
+    let mut config = build_config(platform, &mask_table, &provenance)?;
 
     // Build image
-    let background_iter = background_bytes.into_iter();
-    let mask_iter = mask_bytes.into_iter();
+    let background_iter = data.background_bytes.data().iter();
+    let mask_iter = mask_table.mask_image.data().iter();
 
     let mut count = 0;
 
@@ -51,77 +143,44 @@ pub fn encode(
 
     config.append(&mut image_block);
 
-    // Build mask config
-    let mut mask_block = build_mask_map(pixels_to_mask_id)?;
+    config.append(&mut mask_table.bytes);
 
-    config.append(&mut mask_block);
+    Ok(config)
+}
 
-    // Add ROM
+///
+/// Add the ROM to a file built by `encode_without_rom`, completing it
+///
+pub fn append_rom(file: &mut Vec<u8>, mut rom_data: Vec<u8>) {
     // TODO: Add melody ROM
-    let rom_path = asset_dir.join(&platform.rom.rom);
+    file.append(&mut rom_data);
 
-    let mut rom_data = match fs::read(&rom_path) {
-        Ok(data) => Ok(data),
-        Err(_) => match find_rom_by_hash(&platform.rom.rom_hash, asset_dir) {
-            Ok(data) => Ok(data),
-            Err(err) => Err(format!("{err}\nCould not open ROM {rom_path:?}")),
-        },
-    }?;
-
-    config.append(&mut rom_data);
-
-    let mut game_name = platform.metadata.name.clone();
-
-    if game_name.to_lowercase().starts_with("game & watch:") {
-        game_name = game_name.chars().skip("Game & Watch:".len()).collect();
-    }
-
-    game_name = game_name.replace(":", " -");
-    let game_name = game_name.trim();
-
-    let output_path: PathBuf = output_dir.join(format!("{game_name}.gnw"));
-    fs::write(&output_path, config).unwrap();
-
-    Ok(output_path)
+    // The payload hash covers everything after the config, so it can only be written once the file is complete
+    let payload_hash = Sha1::digest(&file[CONFIG_LENGTH..]);
+    file[PROVENANCE_PAYLOAD_HASH..PROVENANCE_PAYLOAD_HASH + 20].copy_from_slice(&payload_hash);
 }
 
-fn find_rom_by_hash(target_hash: &String, asset_dir: &Path) -> Result<Vec<u8>, String> {
-    for entry in fs::read_dir(asset_dir).expect("Could not open temp directory") {
-        if let Ok(entry) = entry {
-            let mut file = match File::open(entry.path()) {
-                Ok(file) => file,
-                Err(_) => continue,
-            };
-            let mut hasher = Sha1::new();
-            let _ = match io::copy(&mut file, &mut hasher) {
-                Ok(_) => {}
-                Err(_) => continue,
-            };
-            let hash = hasher.finalize();
-
-            let hash = hex::encode(hash);
-
-            if &hash == target_hash {
-                let mut buffer = Vec::new();
-                if let Err(_) = file.seek(io::SeekFrom::Start(0)) {
-                    return Err("Could not reread from file after hash check".into());
-                }
-                if let Err(_) = file.read_to_end(&mut buffer) {
-                    return Err(format!("Could not open SHA matched ROM {:?}", entry.path()));
-                }
-
-                return Ok(buffer);
-            }
-        }
+pub fn write_output(output_path: &Path, data: &[u8]) -> Result<(), String> {
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|err| format!("Could not create output directory {parent:?}: {err}"))?;
     }
 
-    Err(format!("No SHA matched ROM found"))
+    fs::write(output_path, data).map_err(|err| format!("Could not write {output_path:?}: {err}"))
 }
 
-fn build_config(platform: &PlatformSpecification) -> Result<Vec<u8>, String> {
+fn build_config(
+    platform: &PlatformSpecification,
+    mask_table: &MaskTable,
+    provenance: &Provenance,
+) -> Result<Vec<u8>, String> {
     let mut config = Vec::<u8>::with_capacity(0x100);
-    // Version
-    config.push(1);
+    // Version. Version 2 moves the ROM data to make room for an extended mask table
+    config.push(if mask_table.extended_entries.is_some() {
+        2
+    } else {
+        1
+    });
 
     // MPU version
     let version = match platform.device.cpu {
@@ -185,7 +244,7 @@ fn build_config(platform: &PlatformSpecification) -> Result<Vec<u8>, String> {
 
                 s_ports[*index] = Some(bitmap.clone());
             }
-            Port::ACL { bit } => acl_port = bit.clone(),
+            Port::Acl { bit } => acl_port = bit.clone(),
             Port::B { bit } => b_port = bit.clone(),
             Port::BA { bit } => ba_port = bit.clone(),
         }
@@ -253,21 +312,49 @@ fn build_config(platform: &PlatformSpecification) -> Result<Vec<u8>, String> {
     config.push(ground_index);
 
     // Spacer pixels for input mapping
-    for _ in 0..4 {
-        config.push(0);
+    config.extend_from_slice(&[0; 4]);
+
+    // Mask flags, secondary runs, and extended table size
+    let mut mask_flags = 0;
+
+    if mask_table.secondary_runs.is_some() {
+        mask_flags |= MASK_FLAG_SECONDARY_RUNS;
     }
 
-    // Reserved space
-    for _ in 0..0xC9 {
-        config.push(0);
+    if mask_table.extended_entries.is_some() {
+        mask_flags |= MASK_FLAG_EXTENDED_TABLE;
+    }
+
+    config.push(mask_flags);
+
+    if let Some(secondary_runs) = &mask_table.secondary_runs {
+        config.extend_from_slice(&secondary_runs.config_bytes()?);
+    } else {
+        config.extend_from_slice(&[0; 4]);
     }
 
+    config.extend_from_slice(&(mask_table.extended_entries.unwrap_or(0) as u32).to_le_bytes());
+
+    // Metadata location
+    config.extend_from_slice(&(mask_table.metadata_offset as u32).to_le_bytes());
+    config.extend_from_slice(&(mask_table.metadata_length as u16).to_le_bytes());
+
+    // Provenance. The payload hash is filled in once the rest of the file is built
+    config.push(PROVENANCE_VERSION);
+    config.extend_from_slice(&[0; 20]);
+    config.extend_from_slice(&provenance.rom_hash);
+    config.extend_from_slice(&provenance.manifest_hash);
+    config.extend_from_slice(&generator_version());
+
+    // Reserved space
+    config.extend_from_slice(&[0; 0x7A]);
+
     let sha = env!("VERGEN_GIT_SHA");
 
-    if sha.len() < 1 {
+    if sha.is_empty() {
         println!("Unknown git SHA");
 
-        vec![0 as u8; 7].iter().for_each(|c| config.push(*c));
+        [0_u8; 7].iter().for_each(|c| config.push(*c));
     } else {
         sha.chars()
             .take(7)
@@ -278,6 +365,72 @@ fn build_config(platform: &PlatformSpecification) -> Result<Vec<u8>, String> {
     Ok(config)
 }
 
+const METADATA_VERSION: u8 = 1;
+
+///
+/// Build the metadata section, holding the game's display information and input labels as length prefixed UTF-8
+///
+fn build_metadata(
+    platform_name: &str,
+    platform: &PlatformSpecification,
+    include_labels: bool,
+) -> Vec<u8> {
+    let mut metadata = vec![METADATA_VERSION];
+
+    let push_string = |metadata: &mut Vec<u8>, value: &str| {
+        // Truncate to the 255 byte length limit, without splitting a character
+        let mut end = value.len().min(u8::MAX as usize);
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        metadata.push(end as u8);
+        metadata.extend_from_slice(&value.as_bytes()[..end]);
+    };
+
+    push_string(&mut metadata, platform_name);
+    push_string(&mut metadata, &platform.metadata.name);
+    push_string(&mut metadata, &platform.metadata.company);
+    push_string(&mut metadata, &platform.metadata.year);
+
+    // Labels are keyed by the index of their byte in the input mapping
+    let mut labels: Vec<(u8, String)> = vec![];
+
+    let mut add_label = |input_index: usize, action: &Option<NamedAction>| {
+        if let Some(name) = action.as_ref().and_then(|a| a.name.as_ref()) {
+            labels.push((input_index as u8, name.clone()));
+        }
+    };
+
+    for port in &platform.port_map.ports {
+        match port {
+            Port::S { index, bitmap } => {
+                for (bit, action) in bitmap.iter().enumerate() {
+                    add_label(index * 4 + bit, action);
+                }
+            }
+            Port::B { bit } => add_label(32, bit),
+            Port::BA { bit } => add_label(33, bit),
+            Port::Acl { bit } => add_label(34, bit),
+        }
+    }
+
+    labels.sort_by_key(|(input_index, _)| *input_index);
+
+    if !include_labels {
+        labels.clear();
+    }
+
+    metadata.push(labels.len() as u8);
+
+    for (input_index, name) in labels {
+        metadata.push(input_index);
+        push_string(&mut metadata, &name);
+    }
+
+    metadata
+}
+
 fn input_value_for_port(action: NamedAction) -> u8 {
     let mut input: u8 = match action.action {
         Action::JoyUp => 0,
@@ -320,22 +473,23 @@ fn input_value_for_port(action: NamedAction) -> u8 {
     input
 }
 
-const BYTES_PER_ENTRY: usize = 5;
+pub const BYTES_PER_ENTRY: usize = 5;
 const AVERAGE_ENTRIES_PER_ROW: usize = 52;
-const TOTAL_BYTE_LENGTH: usize = BYTES_PER_ENTRY * AVERAGE_ENTRIES_PER_ROW * HEIGHT;
+pub const STANDARD_MASK_ENTRIES: usize = AVERAGE_ENTRIES_PER_ROW * HEIGHT;
 
 fn insert_mask_entry_bytes(
-    output: &mut Vec<u8>,
+    output: &mut [u8],
     byte_index: &mut usize,
     id: u16,
     length: usize,
     start_x: usize,
     y: usize,
 ) -> Result<(), String> {
-    if *byte_index + BYTES_PER_ENTRY > TOTAL_BYTE_LENGTH {
+    if *byte_index + BYTES_PER_ENTRY > output.len() {
         return Err(format!(
-            "More entries ({}) than allowed ({TOTAL_BYTE_LENGTH})",
-            *byte_index + BYTES_PER_ENTRY
+            "More entries ({}) than allowed ({})",
+            *byte_index + BYTES_PER_ENTRY,
+            output.len()
         ));
     }
 
@@ -347,11 +501,311 @@ fn insert_mask_entry_bytes(
     Ok(())
 }
 
-fn build_mask_map(pixels_to_mask_id: &[Option<u16>]) -> Result<Vec<u8>, String> {
+/// Where the optional secondary runs for shared pixels are located in the mask table
+pub struct SecondaryRuns {
+    /// Index of the first secondary entry
+    pub start_entry: usize,
+    /// Number of secondary entries, including the terminators between layers
+    pub entry_count: usize,
+}
+
+impl SecondaryRuns {
+    ///
+    /// The little endian start entry and entry count stored in the config. These are 16 bits, so the secondary runs
+    /// must end within the first 65535 entries, which an extended table can grow past
+    ///
+    fn config_bytes(&self) -> Result<[u8; 4], String> {
+        let end_entry = self.start_entry + self.entry_count;
+
+        if end_entry > u16::MAX as usize {
+            return Err(format!(
+                "Secondary runs end at entry {end_entry}, past the {} entries the mask config can point to. Try fewer shared segments, or `--mask-overflow` strategies that reduce the runs",
+                u16::MAX
+            ));
+        }
+
+        let mut bytes = [0; 4];
+        bytes[..2].copy_from_slice(&(self.start_entry as u16).to_le_bytes());
+        bytes[2..].copy_from_slice(&(self.entry_count as u16).to_le_bytes());
+
+        Ok(bytes)
+    }
+}
+
+pub struct MaskTable {
+    pub bytes: Vec<u8>,
+    /// The lit image, including the color of any pixels filled by the overflow strategies
+    pub mask_image: Pixmap,
+    pub secondary_runs: Option<SecondaryRuns>,
+    /// Set to the number of entries if the table was grown past the standard size
+    pub extended_entries: Option<usize>,
+    /// Byte offset of the metadata section from the start of the table. Zero if there was no room for it
+    pub metadata_offset: usize,
+    pub metadata_length: usize,
+}
+
+///
+/// Build the mask table, applying the overflow strategies in order if the runs don't fit in the standard table. The
+/// first of the `metadata` options that fits in the space left by the runs is stored after them
+///
+fn build_mask(
+    data: &RenderedData,
+    metadata: &[Vec<u8>],
+    options: &EncodeOptions,
+) -> Result<MaskTable, String> {
+    let available_entries = STANDARD_MASK_ENTRIES;
+
+    let mut layers = if options.shared_segments {
+        shared_layers(&data.shared_pixels)
+    } else {
+        vec![]
+    };
+
+    let mut pixels_to_mask_id = data.pixels_to_mask_id.clone();
+    let mut mask_image = data.mask_bytes.clone();
+    let mut needed_entries = count_mask_entries(&pixels_to_mask_id, &layers);
+
+    // Pixels filled and dropped by the overflow strategies, for each segment
+    let mut filled: BTreeMap<u16, usize> = BTreeMap::new();
+    let mut dropped: BTreeMap<u16, usize> = BTreeMap::new();
+
+    let print_usage = |entries: usize| {
+        println!(
+            "Mask runs: {entries} of {available_entries} entries ({:.1}%)",
+            entries as f32 / available_entries as f32 * 100.0
+        );
+    };
+
+    print_usage(needed_entries);
+
+    let mut extended = false;
+
+    for strategy in &options.overflow.strategies {
+        if needed_entries <= available_entries {
+            break;
+        }
+
+        match strategy {
+            MaskOverflowStrategy::MergeGaps => {
+                let mut filled_count = 0;
+
+                // The secondary runs are merged too, as they count against the same table
+                for (layer_index, layer) in std::iter::once(&mut pixels_to_mask_id)
+                    .chain(layers.iter_mut())
+                    .enumerate()
+                {
+                    for (pixel, source) in merge_gaps(layer, options.overflow.merge_gap) {
+                        let id = layer[pixel].unwrap();
+                        *filled.entry(id).or_default() += 1;
+                        filled_count += 1;
+
+                        // Filled pixels are lit with the segment, so they need its lit color. Secondary fills over
+                        // another segment's pixels keep that segment's color
+                        if layer_index == 0 || data.pixels_to_mask_id[pixel].is_none() {
+                            let color = mask_image.pixels()[source];
+                            mask_image.pixels_mut()[pixel] = color;
+                        }
+                    }
+                }
+
+                println!(
+                    "{}",
+                    format!("Mask overflow: Filled {filled_count} gap pixels between runs")
+                        .yellow()
+                );
+            }
+            MaskOverflowStrategy::DropIslands => {
+                let mut dropped_count = 0;
+
+                for layer in std::iter::once(&mut pixels_to_mask_id).chain(layers.iter_mut()) {
+                    for (id, count) in drop_islands(layer, options.overflow.island_threshold) {
+                        *dropped.entry(id).or_default() += count;
+                        dropped_count += count;
+                    }
+                }
+
+                println!(
+                    "{}",
+                    format!("Mask overflow: Dropped {dropped_count} pixels in small islands")
+                        .yellow()
+                );
+            }
+            MaskOverflowStrategy::Extended => {
+                println!(
+                    "{}",
+                    "Mask overflow: Using an extended mask table. This requires core support"
+                        .yellow()
+                );
+                extended = true;
+                break;
+            }
+            MaskOverflowStrategy::Fail => break,
+        }
+
+        needed_entries = count_mask_entries(&pixels_to_mask_id, &layers);
+        print_usage(needed_entries);
+    }
+
+    if needed_entries > available_entries && !extended {
+        return Err(format!(
+            "Mask requires {needed_entries} entries, more than allowed ({available_entries}). Try adding `--mask-overflow` strategies"
+        ));
+    }
+
+    let changed_ids = filled
+        .keys()
+        .chain(dropped.keys())
+        .collect::<BTreeSet<&u16>>();
+
+    if !changed_ids.is_empty() {
+        println!(
+            "{}",
+            format!(
+                "Mask overflow strategies changed the shape of {} segment(s):",
+                changed_ids.len()
+            )
+            .yellow()
+        );
+
+        for id in changed_ids {
+            println!(
+                "{}",
+                format!(
+                    "  {}: {} pixels filled, {} pixels dropped",
+                    format_segment_id(*id),
+                    filled.get(id).unwrap_or(&0),
+                    dropped.get(id).unwrap_or(&0)
+                )
+                .yellow()
+            );
+        }
+    }
+
+    let mut table_entries = needed_entries.max(available_entries);
+
+    if extended {
+        // The extended table is already nonstandard, so it grows to hold all of the metadata too
+        table_entries += 1 + metadata[0].len().div_ceil(BYTES_PER_ENTRY);
+    }
+
+    let (mut bytes, secondary_runs) = build_mask_map(&pixels_to_mask_id, &layers, table_entries)?;
+
+    // Metadata only uses the space the runs leave free, after an all zero terminator entry
+    let metadata_offset = (needed_entries + 1) * BYTES_PER_ENTRY;
+    let fitting_metadata = metadata
+        .iter()
+        .position(|metadata| metadata_offset + metadata.len() <= bytes.len());
+
+    match fitting_metadata {
+        Some(0) => {}
+        Some(_) => println!(
+            "{}",
+            "Mask table is too full for the input labels. They were left out of the metadata"
+                .yellow()
+        ),
+        None => println!(
+            "{}",
+            "Mask table is too full for the metadata. It was left out".yellow()
+        ),
+    }
+
+    let (metadata_offset, metadata_length) = match fitting_metadata {
+        Some(index) => {
+            let metadata = &metadata[index];
+            bytes[metadata_offset..metadata_offset + metadata.len()].copy_from_slice(metadata);
+
+            (metadata_offset, metadata.len())
+        }
+        None => (0, 0),
+    };
+
+    Ok(MaskTable {
+        bytes,
+        mask_image,
+        secondary_runs,
+        extended_entries: if extended { Some(table_entries) } else { None },
+        metadata_offset,
+        metadata_length,
+    })
+}
+
+/// Split the shared pixels into layers, each holding at most one additional ID per pixel, so the layers can be
+/// encoded as normal runs
+fn shared_layers(shared_pixels: &BTreeMap<usize, Vec<u16>>) -> Vec<Vec<Option<u16>>> {
+    let layer_count = shared_pixels
+        .values()
+        .map(|ids| ids.len())
+        .max()
+        .unwrap_or(0);
+
+    (0..layer_count)
+        .map(|layer_index| {
+            let mut layer: Vec<Option<u16>> = vec![None; WIDTH * HEIGHT];
+
+            for (i, ids) in shared_pixels {
+                // Most recently hidden IDs come first
+                layer[*i] = ids.iter().rev().nth(layer_index).copied();
+            }
+
+            layer
+        })
+        .collect()
+}
+
+fn count_mask_entries(pixels_to_mask_id: &[Option<u16>], layers: &[Vec<Option<u16>>]) -> usize {
+    let mut count = count_runs(pixels_to_mask_id);
+
+    if !layers.is_empty() {
+        // Terminators before each layer
+        count += layers.len();
+        count += layers.iter().map(|layer| count_runs(layer)).sum::<usize>();
+    }
+
+    count
+}
+
+fn build_mask_map(
+    pixels_to_mask_id: &[Option<u16>],
+    layers: &[Vec<Option<u16>>],
+    table_entries: usize,
+) -> Result<(Vec<u8>, Option<SecondaryRuns>), String> {
     // 5 bytes per entry
-    let mut output: Vec<u8> = vec![0; TOTAL_BYTE_LENGTH];
+    let mut output: Vec<u8> = vec![0; table_entries * BYTES_PER_ENTRY];
     let mut byte_index = 0;
 
+    insert_mask_runs(&mut output, &mut byte_index, pixels_to_mask_id)?;
+
+    if layers.is_empty() {
+        return Ok((output, None));
+    }
+
+    // Secondary runs are placed after an all zero terminator entry. Cores that don't know about them stop there
+    byte_index += BYTES_PER_ENTRY;
+    let start_entry = byte_index / BYTES_PER_ENTRY;
+
+    for (layer_index, layer) in layers.iter().enumerate() {
+        if layer_index > 0 {
+            // Terminate previous layer
+            byte_index += BYTES_PER_ENTRY;
+        }
+
+        insert_mask_runs(&mut output, &mut byte_index, layer)?;
+    }
+
+    Ok((
+        output,
+        Some(SecondaryRuns {
+            start_entry,
+            entry_count: byte_index / BYTES_PER_ENTRY - start_entry,
+        }),
+    ))
+}
+
+fn insert_mask_runs(
+    output: &mut [u8],
+    byte_index: &mut usize,
+    pixels_to_mask_id: &[Option<u16>],
+) -> Result<(), String> {
     for y in 0..HEIGHT {
         let mut current_id: Option<u16> = None;
         let mut start_x: usize = 0;
@@ -368,12 +822,7 @@ fn build_mask_map(pixels_to_mask_id: &[Option<u16>]) -> Result<Vec<u8>, String>
                         } else {
                             // This is a new segment, finish the old segment and start a new one
                             insert_mask_entry_bytes(
-                                &mut output,
-                                &mut byte_index,
-                                stored_id,
-                                length,
-                                start_x,
-                                y,
+                                output, byte_index, stored_id, length, start_x, y,
                             )?;
 
                             current_id = Some(id);
@@ -394,18 +843,18 @@ fn build_mask_map(pixels_to_mask_id: &[Option<u16>]) -> Result<Vec<u8>, String>
                     // End entry
                     current_id = None;
 
-                    insert_mask_entry_bytes(&mut output, &mut byte_index, id, length, start_x, y)?;
+                    insert_mask_entry_bytes(output, byte_index, id, length, start_x, y)?;
                 }
             }
         }
 
         if let Some(id) = current_id {
             // Clean up straggler at the end of a row
-            insert_mask_entry_bytes(&mut output, &mut byte_index, id, length, start_x, y)?;
+            insert_mask_entry_bytes(output, byte_index, id, length, start_x, y)?;
         }
     }
 
-    Ok(output)
+    Ok(())
 }
 
 fn entry_to_bytes(id: u16, length: usize, start_x: usize, y: usize) -> Vec<u8> {
@@ -418,3 +867,43 @@ fn entry_to_bytes(id: u16, length: usize, start_x: usize, y: usize) -> Vec<u8> {
 
     data.into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secondary_runs_config_bytes() {
+        let runs = SecondaryRuns {
+            start_entry: 0x1234,
+            entry_count: 0x56,
+        };
+
+        assert_eq!(runs.config_bytes(), Ok([0x34, 0x12, 0x56, 0x00]));
+    }
+
+    #[test]
+    fn secondary_runs_must_end_within_16_bits() {
+        let last_fitting = SecondaryRuns {
+            start_entry: 60000,
+            entry_count: u16::MAX as usize - 60000,
+        };
+
+        assert_eq!(last_fitting.config_bytes(), Ok([0x60, 0xEA, 0x9F, 0x15]));
+
+        let past_end = SecondaryRuns {
+            start_entry: 60000,
+            entry_count: u16::MAX as usize - 60000 + 1,
+        };
+
+        assert!(past_end.config_bytes().is_err());
+
+        // Start entries past 16 bits would otherwise wrap into the primary runs
+        let past_start = SecondaryRuns {
+            start_entry: u16::MAX as usize + 2,
+            entry_count: 1,
+        };
+
+        assert!(past_start.config_bytes().is_err());
+    }
+}
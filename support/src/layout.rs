@@ -1,12 +1,16 @@
-use std::{collections::HashMap, fs, path::Path};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
 use serde::Deserialize;
 
-use serde_xml_rs;
-
 #[derive(Debug, Deserialize)]
 pub struct MameLayout {
+    #[serde(default)]
     pub element: Vec<NameElement>,
+    #[serde(default)]
     pub view: Vec<View>,
 }
 
@@ -84,6 +88,23 @@ pub struct Bounds {
     pub height: i32,
 }
 
+impl Bounds {
+    /// The smallest bounds containing both `self` and `other`
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+
+        Bounds {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        }
+    }
+}
+
 // This is written in such garbage form because serde_xml_rs doesn't support untagged enums, so I can't get it to
 // properly build enums with the different Bounds variants
 impl CompleteBounds {
@@ -105,8 +126,8 @@ impl CompleteBounds {
             Bounds {
                 x: xc - width,
                 y: yc - height,
-                width: width,
-                height: height,
+                width,
+                height,
             }
         } else if let (Some(left), Some(right), Some(top), Some(bottom)) =
             (self.left, self.right, self.top, self.bottom)
@@ -129,6 +150,7 @@ pub struct Element {
     #[serde(alias = "element")]
     pub ref_name: String,
     pub bounds: CompleteBounds,
+    #[allow(dead_code)]
     pub blend: Option<BlendType>,
 }
 
@@ -142,36 +164,49 @@ pub enum BlendType {
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Screen {
-    pub index: i32,
+    // Screens are referenced by either index or tag
+    pub index: Option<i32>,
+    pub tag: Option<String>,
     pub bounds: CompleteBounds,
+    #[allow(dead_code)]
     pub blend: Option<BlendType>,
 }
 
+///
+/// Parse and merge every .lay file in the artwork. `default.lay` is read first, so its views take priority
+///
+#[allow(clippy::diverging_sub_expression)]
 pub fn parse_layout(
     temp_dir: &Path,
     specified_layout: Option<&String>,
 ) -> Result<(MameLayout, View), String> {
-    let layout_path = temp_dir.join("default.lay");
-    let layout_file = match fs::read(&layout_path) {
-        Ok(layout_file) => layout_file,
-        Err(_) => {
-            return Err(format!(
-                "Could not find default.lay file at path {layout_path:?}"
-            ))
-        }
+    let layout_paths = find_layout_files(temp_dir)?;
+
+    let mut output = MameLayout {
+        element: vec![],
+        view: vec![],
     };
 
-    // let output: MameLayout = match serde_xml_rs::from_reader(layout_file.as_slice()) {
-    //     Ok(output) => output,
-    //     Err(err) => {
-    //         return Err(format!("Could not parse layout: \"{err}\""))},
-    // };
-    let output: MameLayout = serde_xml_rs::from_reader(layout_file.as_slice()).unwrap();
+    for layout_path in layout_paths {
+        guard!(let Ok(layout_file) = fs::read(&layout_path) else {
+            return Err(format!("Could not read layout file at path {layout_path:?}"));
+        });
+
+        let layout: MameLayout = match serde_xml_rs::from_reader(layout_file.as_slice()) {
+            Ok(layout) => layout,
+            Err(err) => return Err(format!("Could not parse layout {layout_path:?}: \"{err}\"")),
+        };
+
+        output.element.extend(layout.element);
+        output.view.extend(layout.view);
+    }
 
     let mut map = HashMap::<String, View>::new();
 
     for view in output.view.iter() {
-        map.insert(view.name.to_lowercase(), view.clone());
+        // Earlier files win when multiple layouts share a view name
+        map.entry(view.name.to_lowercase())
+            .or_insert_with(|| view.clone());
     }
 
     if let Some(specified_layout) = specified_layout {
@@ -189,7 +224,38 @@ pub fn parse_layout(
     Ok((output, view))
 }
 
-fn select_view<'a>(views: &mut HashMap<String, View>) -> Option<View> {
+#[allow(clippy::diverging_sub_expression)]
+fn find_layout_files(temp_dir: &Path) -> Result<Vec<PathBuf>, String> {
+    guard!(let Ok(entries) = fs::read_dir(temp_dir) else {
+        return Err(format!("Could not open asset directory {temp_dir:?}"));
+    });
+
+    let mut layout_paths = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("lay"))
+        })
+        .collect::<Vec<PathBuf>>();
+
+    layout_paths.sort_by_key(|path| {
+        let is_default = path
+            .file_name()
+            .is_some_and(|name| name.eq_ignore_ascii_case("default.lay"));
+
+        (!is_default, path.clone())
+    });
+
+    if layout_paths.is_empty() {
+        return Err(format!(
+            "Could not find any .lay files at path {temp_dir:?}"
+        ));
+    }
+
+    Ok(layout_paths)
+}
+
+fn select_view(views: &mut HashMap<String, View>) -> Option<View> {
     // Constructed this way to give ordered priority to each view name we want
     let desired_names = vec![
         "backgrounds only (no frame)",
@@ -1,34 +1,114 @@
 #[macro_use]
 extern crate guard;
 
-use std::{collections::HashMap, env::temp_dir, fs, path::PathBuf};
+use std::{
+    collections::HashMap,
+    env::temp_dir,
+    fs,
+    path::{Path, PathBuf},
+    process::exit,
+};
 
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand, ValueEnum};
 
 use colored::Colorize;
 
-use assets::get_assets;
+use image::imageops::FilterType;
+
+use regex::Regex;
+
+use assets::{get_assets, load_rom, AssetPaths};
 use layout::parse_layout;
 use manifest::PlatformSpecification;
 
-use crate::{encode_format::encode, manifest::CPUType, render::RenderedData};
+use crate::{
+    artwork_package::{combine, ArtworkPackage},
+    audit::audit,
+    dat::{apply_dat, parse_dat, DatGame},
+    element_rules::ElementRules,
+    encode_format::{encode, EncodeOptions},
+    layout::Bounds,
+    manifest::{manifest_entry_hashes, CPUType, ElementRuleConfig},
+    mask_budget::{MaskOverflowOptions, MaskOverflowStrategy},
+    mister::{GameListEntry, MisterLayout},
+    naming::{OutputGrouping, OutputNaming},
+    patch::{apply_patch_args, patch_rom},
+    pocket::PocketLayout,
+    project::{generate_project, load_project},
+    query::{list, parse_name_regex, PlatformQuery},
+    render::{Framing, RenderOptions},
+    svg_lint::lint_files,
+    svg_manage::SvgRasterOptions,
+    thumbnail::write_thumbnails,
+    verify::verify_files,
+    watch::{watch, WatchTarget},
+};
 
+mod artwork_package;
 mod assets;
+mod audit;
+mod dat;
+mod element_rules;
 mod encode_format;
 mod layout;
 mod manifest;
+mod mask_budget;
+mod mister;
+mod naming;
+mod patch;
+mod pocket;
+mod project;
+mod query;
 mod render;
+mod segment_report;
+mod svg_lint;
 mod svg_manage;
+mod thumbnail;
+mod verify;
+mod watch;
 
 const WIDTH: usize = 720;
 const HEIGHT: usize = WIDTH;
 
+#[derive(Subcommand, Clone, Debug)]
+enum CommandArg {
+    #[command(flatten)]
+    Filter(FilterArg),
+    /// Check previously generated .gnw files for corruption, and against the current manifest and generator
+    Verify { paths: Vec<PathBuf> },
+    /// List the assets that are present and missing for each game, without generating anything
+    Audit {
+        #[command(subcommand)]
+        filter: Option<FilterArg>,
+    },
+    /// List the games matching the filters, without generating anything
+    List {
+        #[arg(long)]
+        /// Print as JSON instead of a table
+        json: bool,
+        #[command(subcommand)]
+        filter: Option<FilterArg>,
+    },
+    /// Generate a custom game from a folder of loose files, described by its `game.json`
+    Project { path: PathBuf },
+    /// Complete artwork packages (.gnwa) with the user's ROMs, writing .gnw files. Packages are written next to the
+    /// package, or to `--output-path` if provided
+    Combine { packages: Vec<PathBuf> },
+    /// Check segment SVGs for titles and ids that would leave segments missing or mislabeled, reporting line numbers
+    LintSvg {
+        paths: Vec<PathBuf>,
+        #[arg(long)]
+        /// Assign ids to paths that are missing them, rewriting the SVGs in place
+        fix: bool,
+    },
+}
+
 #[derive(Subcommand, Clone, Debug)]
 enum FilterArg {
     /// Match a particular game
     Specific { name: String },
     /// Match the games that use a particular CPU
-    CPU { name: CPUType },
+    Cpu { name: CPUType },
     /// Match the specific CPU types supported by the core currently. These are the SM510 (inc. Tiger) and SM5a CPUs
     Supported,
     /// All game types specified in the manifest.json
@@ -45,28 +125,120 @@ enum CompanyArg {
     Tiger,
     Tronica,
     VTech,
+    Homebrew,
+}
+
+#[derive(ValueEnum, Clone, Debug, PartialEq)]
+enum TargetArg {
+    /// Write the games directly into the output directory
+    Directory,
+    /// Treat the output directory as the root of an Analogue Pocket SD card
+    Pocket,
+    /// Treat the output directory as the root of a MiSTer or MiMiC SD card
+    Mister,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum GroupByArg {
+    Company,
+    Cpu,
+    Year,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum ScreenTypeArg {
+    Single,
+    DualVertical,
+    DualHorizontal,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum FramingArg {
+    /// Use the bounds declared by the layout's view
+    View,
+    /// Fit all of the artwork elements
+    Elements,
+    /// Fit only the LCD screens, zooming in on them
+    Screens,
+    /// Use the rectangle provided by `--frame-bounds`
+    Custom,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum ResampleFilterArg {
+    /// Nearest neighbor. Keeps pixel art crisp
+    Nearest,
+    /// Linear
+    Triangle,
+    /// Cubic
+    CatmullRom,
+    /// Lanczos with a window of 3
+    Lanczos3,
 }
 
 #[derive(Parser, Debug)]
 struct Args {
     #[command(subcommand)]
-    filter: Option<FilterArg>,
+    command: Option<CommandArg>,
 
     #[arg(short = 'i', long)]
-    /// Only the games located in your MAME directory
+    /// Only the games located in your MAME directory or search paths
     installed: bool,
 
     #[arg(short = 'm', long)]
-    /// The path to your MAME directory containing your games
-    mame_path: PathBuf,
+    /// The path to your MAME directory containing your games, in the `roms` and `artwork` folders. Unless verifying,
+    /// either this, `--mame-ini`, or both `--rom-path` and `--artwork-path` are required
+    mame_path: Option<PathBuf>,
+
+    #[arg(long)]
+    /// A `mame.ini` whose `rompath` and `artpath` directories are searched for games
+    mame_ini: Option<PathBuf>,
+
+    #[arg(long = "rom-path", value_name = "PATH")]
+    /// A directory containing ROM sets, as zips or unzipped directories. Can be repeated, and is searched in order
+    /// before the `mame.ini` and MAME directory paths
+    rom_paths: Vec<PathBuf>,
+
+    #[arg(long = "artwork-path", value_name = "PATH")]
+    /// A directory containing artwork sets, as zips or unzipped directories. Can be repeated, and is searched in order
+    /// before the `mame.ini` and MAME directory paths
+    artwork_paths: Vec<PathBuf>,
 
     #[arg(short = 'a', long, default_value = "manifest.json")]
     /// The path to the included manifest file
     manifest_path: PathBuf,
 
     #[arg(short = 'o', long)]
-    /// The path to the final ROM output directory
-    output_path: PathBuf,
+    /// The path to the final ROM output directory. Required unless verifying
+    output_path: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = TargetArg::Directory)]
+    /// The layout of the output directory. `pocket` writes the games to `Assets/gameandwatch/common`, and `mister`
+    /// writes them to `games/Game and Watch`, with an MGL launcher for each game and a `gamelist.json`
+    target: TargetArg,
+
+    #[arg(long, default_value = "_Console/GameAndWatch")]
+    /// The core RBF that `--target mister` launchers start, relative to the SD card root and without the date suffix
+    mister_rbf: String,
+
+    #[arg(long)]
+    /// With `--target pocket`, also write an instance JSON for each game, so it is listed individually in the
+    /// Pocket's menu
+    pocket_instances: bool,
+
+    #[arg(long)]
+    /// Write a thumbnail of each game's artwork as a Pocket library image (`.bin`) and a PNG. With `--target pocket`,
+    /// the library image is written to `Platforms/_images`, otherwise both are written next to the game
+    thumbnails: bool,
+
+    #[arg(long, default_value = "{name}")]
+    /// The file name of each generated game, without extension. Supports the placeholders `{key}` (the manifest key),
+    /// `{name}` (the title without the "Game & Watch:" prefix), `{company}`, `{year}`, and `{cpu}`
+    name_template: String,
+
+    #[arg(long, value_enum)]
+    /// Place each game in a subfolder of the output directory
+    group_by: Option<GroupByArg>,
 
     #[arg(short = 'l', long)]
     /// The layout name specified in the MAME .lay file to use. Will fail if this layout is not found
@@ -76,94 +248,436 @@ struct Args {
     /// Enable debug PNG output
     debug: bool,
 
+    #[arg(long)]
+    /// Rebuild a single game (such as `specific gnw_ball`) or a project whenever its files change, printing the
+    /// segments whose pixel counts changed. Implies `--debug`
+    watch: bool,
+
+    #[arg(long, value_enum, default_value_t = FramingArg::Elements)]
+    /// The region of the layout that is scaled to fill the output image
+    framing: FramingArg,
+
+    #[arg(long, value_parser = parse_bounds, required_if_eq("framing", "custom"))]
+    /// The rectangle to frame when using `--framing custom`, in layout coordinates. Formatted as `x,y,width,height`
+    frame_bounds: Option<Bounds>,
+
+    #[arg(long, value_enum, default_value_t = ResampleFilterArg::CatmullRom)]
+    /// The filter used when resizing artwork element images
+    element_filter: ResampleFilterArg,
+
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=8))]
+    /// Rasterize the LCD at this multiple of the output resolution and downsample it, smoothing segment edges
+    svg_supersample: u32,
+
+    #[arg(long, default_value_t = 0.0, value_parser = parse_coverage)]
+    /// The fraction of a pixel (0.0 to 1.0) a segment must cover before that pixel is assigned to the segment. At 0.0,
+    /// any coverage is enough
+    segment_coverage: f32,
+
+    #[arg(long)]
+    /// Print the pixel count and bounds of every LCD segment in each game. Problems are always printed
+    segment_report: bool,
+
+    #[arg(long)]
+    /// Encode the IDs of pixels shared by multiple overlapping segments as secondary mask runs. By default, the last
+    /// segment drawn at a pixel wins
+    shared_segments: bool,
+
+    #[arg(long, value_enum, value_delimiter = ',')]
+    /// Strategies applied in order when a game has more mask runs than fit in the mask table. By default, the game
+    /// fails. `merge-gaps` and `drop-islands` change segment shapes, and list each segment they changed
+    mask_overflow: Vec<MaskOverflowStrategy>,
+
+    #[arg(long, default_value_t = 2)]
+    /// The largest gap, in pixels, between runs of the same segment that the `merge-gaps` strategy will fill
+    merge_gap: usize,
+
+    #[arg(long, default_value_t = 4)]
+    /// Groups of segment pixels smaller than this are removed by the `drop-islands` strategy
+    island_threshold: usize,
+
+    #[arg(long = "include-element", value_name = "PATTERN")]
+    /// Always render layout elements matching this pattern. Patterns are globs (`gradient*`), or regexes when wrapped
+    /// in slashes (`/^fix\d+$/`). Can be repeated
+    include_elements: Vec<String>,
+
+    #[arg(long = "exclude-element", value_name = "PATTERN")]
+    /// Skip layout elements matching this pattern. See `--include-element` for the syntax. Can be repeated
+    exclude_elements: Vec<String>,
+
+    #[arg(long)]
+    /// A JSON file containing `include` and `exclude` element pattern lists, applied to every game
+    element_rules_path: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Render the elements that are skipped by default (dust, bubbles, unit, backdrop, and gradients)
+    no_default_element_rules: bool,
+
+    #[arg(long)]
+    /// A MAME `-listxml` output or Logiqx DAT file. ROM names, hashes, parent sets, and melody ROMs are taken from
+    /// it instead of the manifest, and any disagreements are reported
+    dat_path: Option<PathBuf>,
+
+    #[arg(long, conflicts_with_all = ["target", "pocket_instances", "allow_rom_mismatch"])]
+    /// Write artwork packages (.gnwa) containing everything but the ROM, which can be shared without the ROM. Use
+    /// the `combine` command to complete them with a ROM
+    artwork_only: bool,
+
+    #[arg(long = "patch", value_name = "KEY=PATH", value_parser = parse_patch_arg)]
+    /// Apply an IPS or BPS patch to a game's ROM, replacing any patch from the manifest. The ROM is verified against
+    /// the manifest before patching. Can be repeated
+    patches: Vec<(String, PathBuf)>,
+
+    #[arg(long = "patched-hash", value_name = "KEY=SHA1", value_parser = parse_patched_hash_arg)]
+    /// The expected SHA-1 of a game's ROM after patching. Can be repeated
+    patched_hashes: Vec<(String, String)>,
+
+    #[arg(long)]
+    /// Embed a ROM whose SHA-1 doesn't match the manifest, with a warning, instead of failing
+    allow_rom_mismatch: bool,
+
+    #[arg(long)]
+    /// Embed a patched ROM without a patched SHA-1, or whose SHA-1 doesn't match it, with a warning, instead of failing
+    allow_patched_rom_mismatch: bool,
+
     ///////////////////
 
-    // Company filtering
-    #[arg(short, long)]
-    /// Filter to Nintendo games
-    nintendo: bool,
+    // Filtering
+    #[arg(long = "company", value_enum, value_name = "COMPANY")]
+    /// Only games from this company. Can be repeated
+    companies: Vec<CompanyArg>,
+
+    #[arg(long = "exclude-company", value_enum, value_name = "COMPANY")]
+    /// Skip games from this company. Can be repeated
+    exclude_companies: Vec<CompanyArg>,
+
+    #[arg(long = "cpu", value_enum, value_name = "CPU")]
+    /// Only games using this CPU. Can be repeated
+    cpus: Vec<CPUType>,
+
+    #[arg(long = "screen", value_enum, value_name = "SCREEN")]
+    /// Only games with this screen layout. Can be repeated
+    screens: Vec<ScreenTypeArg>,
+
+    #[arg(long)]
+    /// Only games released in or after this year. Uncertain years, like `198?`, match if any of their possible years
+    /// are in range
+    min_year: Option<u16>,
 
-    #[arg(short, long)]
-    /// Filter to Elektronika games
-    elektronika: bool,
+    #[arg(long)]
+    /// Only games released in or before this year
+    max_year: Option<u16>,
 
-    #[arg(short, long)]
-    /// Filter to Konami games
-    konami: bool,
+    #[arg(long, value_parser = parse_name_regex)]
+    /// Only games whose manifest key or title matches this case insensitive regex
+    name: Option<Regex>,
 
-    #[arg(short = 'c', long)]
-    /// Filter to Nelsonic games
-    nelsonic: bool,
+    #[arg(long = "exclude", value_name = "KEY")]
+    /// Skip the game with this manifest key. Can be repeated
+    exclude: Vec<String>,
+}
+
+#[allow(clippy::diverging_sub_expression)]
+fn parse_bounds(value: &str) -> Result<Bounds, String> {
+    let values = value
+        .split(',')
+        .map(|v| v.trim().parse::<i32>())
+        .collect::<Result<Vec<i32>, _>>()
+        .map_err(|err| format!("Could not parse bounds \"{value}\": {err}"))?;
+
+    guard!(let [x, y, width, height] = values[..] else {
+        return Err(format!("Bounds \"{value}\" must have the form x,y,width,height"));
+    });
+
+    if width <= 0 || height <= 0 {
+        return Err(format!("Bounds \"{value}\" must have a positive size"));
+    }
+
+    Ok(Bounds {
+        x,
+        y,
+        width,
+        height,
+    })
+}
 
-    #[arg(short, long)]
-    /// Filter to Tiger Electronics games
-    tiger: bool,
+#[allow(clippy::diverging_sub_expression)]
+fn parse_patch_arg(value: &str) -> Result<(String, PathBuf), String> {
+    guard!(let Some((name, path)) = value.split_once('=') else {
+        return Err(format!("Patch \"{value}\" must have the form KEY=PATH"));
+    });
 
-    #[arg(short = 'r', long)]
-    /// Filter to Tronica games
-    tronica: bool,
+    Ok((name.trim().to_string(), PathBuf::from(path)))
+}
 
-    #[arg(short, long)]
-    /// Filter to VTech games
-    vtech: bool,
+#[allow(clippy::diverging_sub_expression)]
+fn parse_patched_hash_arg(value: &str) -> Result<(String, String), String> {
+    guard!(let Some((name, hash)) = value.split_once('=') else {
+        return Err(format!("Patched hash \"{value}\" must have the form KEY=SHA1"));
+    });
+
+    let hash = hash.trim().to_lowercase();
+
+    if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Patched hash \"{hash}\" is not a SHA-1"));
+    }
 
-    #[arg(short = 'b', long)]
-    /// Filter to Homebrew games
-    homebrew: bool,
+    Ok((name.trim().to_string(), hash))
+}
+
+fn parse_coverage(value: &str) -> Result<f32, String> {
+    match value.trim().parse::<f32>() {
+        Ok(coverage) if (0.0..1.0).contains(&coverage) => Ok(coverage),
+        Ok(_) => Err(format!(
+            "Coverage {value} must be at least 0.0 and less than 1.0"
+        )),
+        Err(err) => Err(format!("Could not parse coverage \"{value}\": {err}")),
+    }
 }
 
 fn main() {
     let args = Args::parse();
 
-    let temp_dir = temp_dir().join("gnw");
+    if let Some(CommandArg::LintSvg { paths, fix }) = &args.command {
+        let success = lint_files(paths, *fix);
 
-    let manifest_file = fs::read(args.manifest_path).expect("Could not find manifest file");
+        exit(if success { 0 } else { 1 });
+    }
 
-    let manifest: HashMap<String, PlatformSpecification> =
-        serde_json::from_slice(manifest_file.as_slice()).expect("Could not parse manifest file");
+    let mut element_rule_config = if let Some(path) = &args.element_rules_path {
+        let file = fs::read(path).expect("Could not find element rules file");
 
-    let output_path = args
-        .output_path
-        .canonicalize()
-        .expect("Could not find output path");
+        serde_json::from_slice::<ElementRuleConfig>(file.as_slice())
+            .expect("Could not parse element rules file")
+    } else {
+        ElementRuleConfig::default()
+    };
+
+    element_rule_config
+        .include
+        .extend(args.include_elements.iter().cloned());
+    element_rule_config
+        .exclude
+        .extend(args.exclude_elements.iter().cloned());
 
-    let company_filter = {
-        let mut filter = vec![];
+    let element_rules =
+        match ElementRules::new(&element_rule_config, !args.no_default_element_rules) {
+            Ok(rules) => rules,
+            Err(err) => {
+                println!("{err}");
+                return;
+            }
+        };
 
-        if args.nintendo {
-            filter.push("nintendo");
+    let render_options = RenderOptions {
+        framing: match args.framing {
+            FramingArg::View => Framing::View,
+            FramingArg::Elements => Framing::Elements,
+            FramingArg::Screens => Framing::Screens,
+            // Presence is enforced by clap
+            FramingArg::Custom => Framing::Custom(args.frame_bounds.clone().unwrap()),
+        },
+        element_rules,
+        element_filter: match args.element_filter {
+            ResampleFilterArg::Nearest => FilterType::Nearest,
+            ResampleFilterArg::Triangle => FilterType::Triangle,
+            ResampleFilterArg::CatmullRom => FilterType::CatmullRom,
+            ResampleFilterArg::Lanczos3 => FilterType::Lanczos3,
+        },
+        svg: SvgRasterOptions {
+            supersample: args.svg_supersample,
+            coverage_threshold: args.segment_coverage,
+        },
+        segment_report: args.segment_report,
+        debug: args.debug || args.watch,
+    };
+
+    let output_naming = match OutputNaming::new(
+        &args.name_template,
+        args.group_by.as_ref().map(|group_by| match group_by {
+            GroupByArg::Company => OutputGrouping::Company,
+            GroupByArg::Cpu => OutputGrouping::Cpu,
+            GroupByArg::Year => OutputGrouping::Year,
+        }),
+    ) {
+        Ok(naming) => naming,
+        Err(err) => {
+            println!("{err}");
+            return;
         }
+    };
+
+    let encode_options = EncodeOptions {
+        shared_segments: args.shared_segments,
+        overflow: MaskOverflowOptions {
+            strategies: args.mask_overflow.clone(),
+            merge_gap: args.merge_gap,
+            island_threshold: args.island_threshold,
+        },
+    };
+
+    let temp_dir = temp_dir().join("gnw");
 
-        if args.elektronika {
-            filter.push("elektronika");
-            filter.push("bootleg (elektronika)");
+    let dat = args.dat_path.as_ref().map(|path| match parse_dat(path) {
+        Ok(dat) => dat,
+        Err(err) => {
+            println!("{err}");
+            exit(1);
         }
+    });
+
+    let apply_dat = |manifest: &mut HashMap<String, PlatformSpecification>,
+                     dat: &Option<HashMap<String, DatGame>>| {
+        if let Some(dat) = dat {
+            let mismatch_count = apply_dat(manifest, dat);
 
-        if args.konami {
-            filter.push("konami");
+            if mismatch_count > 0 {
+                println!(
+                    "{mismatch_count} manifest entries disagree with the DAT. Using the DAT ROMs\n"
+                );
+            }
         }
+    };
 
-        if args.nelsonic {
-            filter.push("nelsonic");
+    let apply_patches = |manifest: &mut HashMap<String, PlatformSpecification>| {
+        let manifest_dir = args.manifest_path.parent().unwrap_or(Path::new(""));
+
+        if let Err(err) =
+            apply_patch_args(manifest, manifest_dir, &args.patches, &args.patched_hashes)
+        {
+            println!("{err}");
+            exit(1);
         }
+    };
+
+    if let Some(CommandArg::Verify { paths }) = &args.command {
+        // Manifest checks are optional, so files can be verified away from the generator checkout
+        let manifest = fs::read(&args.manifest_path).ok().and_then(|file| {
+            let mut manifest: HashMap<String, PlatformSpecification> =
+                serde_json::from_slice(file.as_slice()).ok()?;
+            apply_dat(&mut manifest, &dat);
+            apply_patches(&mut manifest);
+            let hashes = manifest_entry_hashes(file.as_slice()).ok()?;
+
+            Some((manifest, hashes))
+        });
 
-        if args.tiger {
-            filter.push("tiger");
+        if manifest.is_none() {
+            println!("Could not load manifest. Skipping manifest checks");
         }
 
-        if args.tronica {
-            filter.push("tronica");
+        let success = verify_files(paths, manifest.as_ref().map(|(m, h)| (m, h)));
+
+        exit(if success { 0 } else { 1 });
+    }
+
+    let missing_argument = |message: &str| -> ! {
+        Args::command()
+            .error(ErrorKind::MissingRequiredArgument, message)
+            .exit()
+    };
+
+    if let Some(CommandArg::Project { path }) = &args.command {
+        let output_path = match &args.output_path {
+            Some(path) => path,
+            None => missing_argument("--output-path is required when generating"),
+        };
+
+        if args.watch {
+            let target = WatchTarget::Project {
+                path: path.clone(),
+                output_dir: output_path.clone(),
+                naming: &output_naming,
+            };
+
+            watch(&target, &render_options, &encode_options);
+            return;
         }
 
-        if args.vtech {
-            filter.push("vtech");
+        let result = load_project(path).and_then(|project| {
+            println!("Processing project {}\n", project.key.green());
+
+            generate_project(
+                &project,
+                output_path,
+                &output_naming,
+                &render_options,
+                &encode_options,
+            )
+        });
+
+        match result {
+            Ok(game_path) => println!("Successfully created project at {}", game_path.display()),
+            Err(err) => {
+                println!("{err}");
+                exit(1);
+            }
+        }
+
+        return;
+    }
+
+    let asset_paths = match AssetPaths::new(
+        args.mame_path.as_deref(),
+        args.mame_ini.as_deref(),
+        &args.rom_paths,
+        &args.artwork_paths,
+    ) {
+        Ok(paths) => paths,
+        Err(err) => {
+            println!("{err}");
+            exit(1);
         }
+    };
 
-        if args.homebrew {
-            filter.push("homebrew");
+    if let Some(CommandArg::Combine { packages }) = &args.command {
+        if asset_paths.rom_paths.is_empty() {
+            missing_argument("--mame-path, --mame-ini, or --rom-path is required when combining");
         }
 
-        filter
+        let mut fail_count = 0;
+
+        for package_path in packages {
+            let output_path = match &args.output_path {
+                Some(path) => path.join(package_path.file_name().unwrap_or_default()),
+                None => package_path.clone(),
+            }
+            .with_extension("gnw");
+
+            match combine(package_path, &asset_paths, &temp_dir, &output_path) {
+                Ok(_) => println!("Combined {}", output_path.display().to_string().green()),
+                Err(err) => {
+                    println!("{err}");
+                    println!(
+                        "{}",
+                        format!("Failing package {}\n", package_path.display()).red()
+                    );
+                    fail_count += 1;
+                }
+            }
+        }
+
+        exit(if fail_count == 0 { 0 } else { 1 });
+    }
+
+    let manifest_file = fs::read(&args.manifest_path).expect("Could not find manifest file");
+
+    let mut manifest: HashMap<String, PlatformSpecification> =
+        serde_json::from_slice(manifest_file.as_slice()).expect("Could not parse manifest file");
+    apply_dat(&mut manifest, &dat);
+    apply_patches(&mut manifest);
+    let manifest_hashes =
+        manifest_entry_hashes(manifest_file.as_slice()).expect("Could not parse manifest file");
+
+    let query = PlatformQuery {
+        companies: args.companies.clone(),
+        cpus: args.cpus.clone(),
+        screens: args.screens.clone(),
+        min_year: args.min_year,
+        max_year: args.max_year,
+        name: args.name.clone(),
+        exclude: args.exclude.clone(),
+        exclude_companies: args.exclude_companies.clone(),
     };
 
     let filter_platforms =
@@ -174,14 +688,26 @@ fn main() {
                 .map(|(n, p)| (n.clone(), p))
                 .collect::<Vec<(String, &PlatformSpecification)>>();
 
-            if result.len() > 0 {
+            if !result.is_empty() {
                 Some(result)
             } else {
                 None
             }
         };
 
-    let platforms: Option<Vec<(String, &PlatformSpecification)>> = match &args.filter {
+    let filter = match &args.command {
+        Some(CommandArg::Filter(filter)) => Some(filter),
+        Some(CommandArg::Audit { filter }) | Some(CommandArg::List { filter, .. }) => {
+            filter.as_ref()
+        }
+        Some(CommandArg::Verify { .. })
+        | Some(CommandArg::Combine { .. })
+        | Some(CommandArg::Project { .. })
+        | Some(CommandArg::LintSvg { .. })
+        | None => None,
+    };
+
+    let platforms: Option<Vec<(String, &PlatformSpecification)>> = match filter {
         Some(FilterArg::Specific { name }) => {
             let trimmed_name = name.trim().to_string();
 
@@ -194,11 +720,11 @@ fn main() {
         Some(FilterArg::Supported) => {
             filter_platforms(vec![CPUType::SM510, CPUType::SM510Tiger, CPUType::SM5a])
         }
-        Some(FilterArg::CPU { name }) => filter_platforms(vec![name.clone()]),
+        Some(FilterArg::Cpu { name }) => filter_platforms(vec![name.clone()]),
         Some(FilterArg::All) | None => Some(manifest.iter().map(|(n, p)| (n.clone(), p)).collect()),
     };
 
-    let installed = if args.filter.is_some() {
+    let installed = if args.command.is_some() {
         args.installed
     } else {
         true
@@ -211,22 +737,91 @@ fn main() {
 
     platforms.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
 
-    let platforms = platforms.iter().filter(|(_, p)| {
-        if company_filter.len() > 0 {
-            for filter in &company_filter {
-                if p.metadata.company.to_lowercase().starts_with(filter) {
-                    return true;
-                }
+    let platforms = platforms
+        .into_iter()
+        .filter(|(name, p)| query.matches(name, p))
+        .collect::<Vec<(String, &PlatformSpecification)>>();
+
+    if let Some(CommandArg::List { json, .. }) = &args.command {
+        list(&platforms, *json);
+        return;
+    }
+
+    if asset_paths.rom_paths.is_empty() || asset_paths.artwork_paths.is_empty() {
+        missing_argument(
+            "--mame-path, --mame-ini, or both --rom-path and --artwork-path are required",
+        );
+    }
+
+    if let Some(CommandArg::Audit { .. }) = &args.command {
+        audit(&platforms, &asset_paths);
+        return;
+    }
+
+    let output_path = match &args.output_path {
+        Some(path) => path.canonicalize().expect("Could not find output path"),
+        None => missing_argument("--output-path is required when generating"),
+    };
+
+    let pocket_layout = if args.target == TargetArg::Pocket {
+        match PocketLayout::new() {
+            Ok(layout) => Some(layout),
+            Err(err) => {
+                println!("{err}");
+                return;
             }
-        } else {
-            return true;
         }
+    } else {
+        None
+    };
 
-        false
-    });
+    let mister_layout = if args.target == TargetArg::Mister {
+        Some(MisterLayout::new(&args.mister_rbf))
+    } else {
+        None
+    };
+
+    let games_dir = match (&pocket_layout, &mister_layout) {
+        (Some(layout), _) => layout.common_dir(&output_path),
+        (_, Some(layout)) => layout.games_dir(&output_path),
+        (None, None) => output_path.clone(),
+    };
+
+    if args.watch {
+        guard!(let [(name, platform)] = &platforms[..] else {
+            println!("--watch requires a single game, such as `specific gnw_ball`");
+            exit(1);
+        });
+
+        let target = WatchTarget::Game {
+            name,
+            platform,
+            manifest_hash: manifest_hashes[name],
+            paths: &asset_paths,
+            asset_dir: temp_dir.join(name),
+            layout_name: args.layout.as_ref(),
+            output_path: games_dir.join(output_naming.relative_path(name, platform)),
+            allow_rom_mismatch: args.allow_rom_mismatch,
+            allow_patched_rom_mismatch: args.allow_patched_rom_mismatch,
+        };
+
+        println!(
+            "Debug images are written to {}",
+            temp_dir.join(name).display()
+        );
+
+        watch(&target, &render_options, &encode_options);
+        return;
+    }
+
+    let mut game_list = vec![];
+
+    // Lowercased, as FAT32 and exFAT are case insensitive
+    let mut written_paths: HashMap<String, &String> = HashMap::new();
 
     let mut success_count = 0;
     let mut skip_count = 0;
+    let mut segment_warning_count = 0;
     let mut fail_count = 0;
     let mut platform_count = 0;
 
@@ -237,14 +832,14 @@ fn main() {
         fail_count += 1;
     };
 
-    for (name, platform) in platforms {
+    for (name, platform) in &platforms {
         platform_count += 1;
         let asset_dir = temp_dir.join(name.clone());
 
         println!("-------------------------");
         println!("Processing device {}\n", name.green());
 
-        if let Err(err) = get_assets(&name, &platform.rom.rom_owner, &args.mame_path, &asset_dir) {
+        if let Err(err) = get_assets(name, &platform.rom.rom_owner, &asset_paths, &asset_dir) {
             if !installed {
                 // Only fail if we're not looking for only owned games
                 fail(name, err);
@@ -260,6 +855,49 @@ fn main() {
             continue;
         }
 
+        let mut relative_game_path = output_naming.relative_path(name, platform);
+
+        if args.artwork_only {
+            relative_game_path.set_extension("gnwa");
+        }
+
+        let game_path = games_dir.join(&relative_game_path);
+
+        // Pocket library images share a single folder, so they can collide even when grouped games don't
+        let thumbnail_path = match &pocket_layout {
+            Some(layout) => layout
+                .images_dir(&output_path)
+                .join(relative_game_path.file_name().unwrap())
+                .with_extension("bin"),
+            None => game_path.with_extension("bin"),
+        };
+
+        let mut output_paths = vec![&game_path];
+
+        if args.thumbnails {
+            output_paths.push(&thumbnail_path);
+        }
+
+        let output_keys = output_paths
+            .iter()
+            .map(|path| path.to_string_lossy().to_lowercase())
+            .collect::<Vec<String>>();
+
+        let collision = output_paths
+            .iter()
+            .zip(&output_keys)
+            .find_map(|(path, key)| written_paths.get(key).map(|other_name| (path, other_name)));
+
+        if let Some((path, other_name)) = collision {
+            fail(
+                name,
+                format!(
+                    "Output {path:?} collides with device {other_name}. Use a `--name-template` containing {{key}} to separate them"
+                ),
+            );
+            continue;
+        }
+
         let (layout_manifest, layout) = match parse_layout(&asset_dir, args.layout.as_ref()) {
             Ok(layout) => layout,
             Err(err) => {
@@ -268,17 +906,13 @@ fn main() {
             }
         };
 
-        let RenderedData {
-            background_bytes,
-            mask_bytes,
-            pixels_to_mask_id,
-        } = match render::render(
-            &name,
+        let rendered_data = match render::render(
+            name,
             &layout,
             &layout_manifest,
-            &platform,
+            platform,
             &asset_dir,
-            args.debug,
+            &render_options,
         ) {
             Ok(data) => data,
             Err(err) => {
@@ -287,30 +921,130 @@ fn main() {
             }
         };
 
-        let data_path = encode(
-            background_bytes.data(),
-            mask_bytes.data(),
-            pixels_to_mask_id.as_slice(),
-            platform,
+        if rendered_data.segment_report.has_problems() {
+            segment_warning_count += 1;
+        }
+
+        if args.artwork_only {
+            let result = ArtworkPackage::new(
+                name,
+                &rendered_data,
+                platform,
+                &manifest_hashes[name],
+                &encode_options,
+            )
+            .and_then(|package| package.write(&game_path));
+
+            match result {
+                Ok(_) => {
+                    println!(
+                        "Successfully created artwork package {} at {}\n",
+                        name.green(),
+                        game_path.display()
+                    );
+                    written_paths.extend(output_keys.into_iter().map(|key| (key, name)));
+                    success_count += 1;
+                }
+                Err(err) => fail(name, err),
+            }
+            continue;
+        }
+
+        let rom_data = match load_rom(
+            name,
+            &platform.rom,
+            &asset_paths,
             &asset_dir,
-            &output_path,
+            args.allow_rom_mismatch,
+        ) {
+            Ok(data) => data,
+            Err(err) => {
+                fail(name, err);
+                continue;
+            }
+        };
+
+        let rom_data = match &platform.rom.patch {
+            Some(patch) => match patch_rom(&rom_data, patch, args.allow_patched_rom_mismatch) {
+                Ok(data) => data,
+                Err(err) => {
+                    fail(name, err);
+                    continue;
+                }
+            },
+            None => rom_data,
+        };
+
+        let result = encode(
+            name,
+            &rendered_data,
+            platform,
+            &manifest_hashes[name],
+            rom_data,
+            &game_path,
+            &encode_options,
         );
 
-        match data_path {
-            Ok(path) => {
+        match result {
+            Ok(_) => {
+                if let (Some(layout), true) = (&pocket_layout, args.pocket_instances) {
+                    if let Err(err) = layout.write_instance_json(&output_path, &relative_game_path)
+                    {
+                        fail(name, err);
+                        continue;
+                    }
+                }
+
+                if let Some(layout) = &mister_layout {
+                    match layout.write_mgl(&output_path, &relative_game_path) {
+                        Ok(launcher_path) => game_list.push(GameListEntry::new(
+                            name,
+                            platform,
+                            &relative_game_path,
+                            &launcher_path,
+                        )),
+                        Err(err) => {
+                            fail(name, err);
+                            continue;
+                        }
+                    }
+                }
+
+                if args.thumbnails {
+                    if let Err(err) = write_thumbnails(
+                        &rendered_data.mask_bytes,
+                        &thumbnail_path,
+                        &game_path.with_extension("png"),
+                    ) {
+                        fail(name, err);
+                        continue;
+                    }
+                }
+
                 println!(
                     "Successfully created device {} at {}\n",
                     name.green(),
-                    path.display()
+                    game_path.display()
                 );
+                written_paths.extend(output_keys.into_iter().map(|key| (key, name)));
                 success_count += 1;
             }
             Err(err) => fail(name, err),
         }
     }
 
+    if let Some(layout) = &mister_layout {
+        if let Err(err) = layout.write_game_list(&output_path, &game_list) {
+            println!("{}", err.red());
+        }
+    }
+
     println!("-------------------------");
     println!(
         "Total: {platform_count}, Success: {success_count}, Fail: {fail_count}, Skip: {skip_count}",
     );
+
+    if segment_warning_count > 0 {
+        println!("{segment_warning_count} device(s) had segment warnings");
+    }
 }
@@ -1,5 +1,8 @@
+use std::{collections::HashMap, path::PathBuf};
+
 use clap::ValueEnum;
 use serde::Deserialize;
+use sha1::{Digest, Sha1};
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -8,6 +11,40 @@ pub struct PlatformSpecification {
     pub port_map: PlatformPortMapping,
     pub metadata: Metdata,
     pub rom: ROMName,
+    /// Per game overrides for which layout elements are rendered
+    pub elements: Option<ElementRuleConfig>,
+}
+
+///
+/// Hash each manifest entry, so generated files can record exactly which entry they were built from. Keys are
+/// serialized in sorted order, so the hash doesn't depend on the formatting of the manifest file
+///
+pub fn manifest_entry_hashes(manifest_file: &[u8]) -> Result<HashMap<String, [u8; 20]>, String> {
+    let entries: HashMap<String, serde_json::Value> = serde_json::from_slice(manifest_file)
+        .map_err(|err| format!("Could not parse manifest file: {err}"))?;
+
+    Ok(entries
+        .into_iter()
+        .map(|(name, entry)| {
+            let hash = entry_hash(&entry);
+
+            (name, hash)
+        })
+        .collect())
+}
+
+pub fn entry_hash(entry: &serde_json::Value) -> [u8; 20] {
+    Sha1::digest(entry.to_string().as_bytes()).into()
+}
+
+/* Element Rules */
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ElementRuleConfig {
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
 }
 
 /* ROM */
@@ -19,6 +56,17 @@ pub struct ROMName {
     pub melody: Option<String>,
     pub rom_owner: Option<String>,
     pub rom_hash: String,
+    /// Applied to the ROM after it's verified against `rom_hash`
+    pub patch: Option<RomPatch>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RomPatch {
+    /// An IPS or BPS patch, relative to the manifest
+    pub file: PathBuf,
+    /// The SHA-1 of the patched ROM
+    pub patched_hash: Option<String>,
 }
 
 /* Metdata */
@@ -37,6 +85,9 @@ pub struct Metdata {
 pub struct PresetDefinition {
     pub cpu: CPUType,
     pub screen: Screen,
+    /// Explicit SVG file names for each screen, in screen index order. If unset, the names are derived from the
+    /// device name and screen type (`gnw_name_top.svg`)
+    pub svgs: Option<Vec<String>>,
 }
 
 #[derive(PartialEq, Clone, Debug, Deserialize, ValueEnum)]
@@ -77,6 +128,7 @@ pub struct Size {
 #[serde(rename_all = "camelCase")]
 pub struct PlatformPortMapping {
     pub ports: Vec<Port>,
+    #[allow(dead_code)]
     pub include: Option<String>,
     pub ground_last_index: Option<u8>,
 }
@@ -89,7 +141,7 @@ pub enum Port {
         index: usize,
         bitmap: [Option<NamedAction>; 4],
     },
-    ACL {
+    Acl {
         bit: Option<NamedAction>,
     },
     B {
@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+
+use clap::ValueEnum;
+
+use crate::{HEIGHT, WIDTH};
+
+/// Ways to reduce the number of mask runs when a game doesn't fit in the mask table
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum MaskOverflowStrategy {
+    /// Fill small gaps between two runs of the same segment on a row, merging them into one run
+    MergeGaps,
+    /// Remove tiny isolated groups of segment pixels
+    DropIslands,
+    /// Grow the mask table past its standard size. This requires a core that supports the extended table
+    Extended,
+    /// Stop applying strategies, failing the game if it still doesn't fit
+    Fail,
+}
+
+pub struct MaskOverflowOptions {
+    /// Strategies to apply, in order, until the mask fits
+    pub strategies: Vec<MaskOverflowStrategy>,
+    /// The largest gap, in pixels, filled by `MergeGaps`
+    pub merge_gap: usize,
+    /// Groups with fewer pixels than this are removed by `DropIslands`
+    pub island_threshold: usize,
+}
+
+///
+/// Count the mask runs required to encode a pixel to segment ID map
+///
+pub fn count_runs(pixels_to_mask_id: &[Option<u16>]) -> usize {
+    let mut count = 0;
+
+    for y in 0..HEIGHT {
+        let mut previous: Option<u16> = None;
+
+        for x in 0..WIDTH {
+            let id = pixels_to_mask_id[y * WIDTH + x];
+
+            if id.is_some() && id != previous {
+                count += 1;
+            }
+
+            previous = id;
+        }
+    }
+
+    count
+}
+
+///
+/// Fill gaps of up to `max_gap` empty pixels between runs of the same segment on a row. Returns each filled pixel,
+/// paired with the last pixel of the run before the gap, which it should take the lit color of
+///
+#[allow(clippy::diverging_sub_expression)]
+pub fn merge_gaps(pixels_to_mask_id: &mut [Option<u16>], max_gap: usize) -> Vec<(usize, usize)> {
+    let mut filled = vec![];
+
+    for y in 0..HEIGHT {
+        let row_start = y * WIDTH;
+        let row = &mut pixels_to_mask_id[row_start..row_start + WIDTH];
+
+        // The segment and end position of the last run seen on this row
+        let mut last_run: Option<(u16, usize)> = None;
+
+        for x in 0..WIDTH {
+            guard!(let Some(id) = row[x] else {
+                continue;
+            });
+
+            if let Some((last_id, last_end)) = last_run {
+                let gap = x - last_end - 1;
+
+                if last_id == id && gap > 0 && gap <= max_gap {
+                    for (gap_x, pixel) in row.iter_mut().enumerate().take(x).skip(last_end + 1) {
+                        *pixel = Some(id);
+                        filled.push((row_start + gap_x, row_start + last_end));
+                    }
+                }
+            }
+
+            last_run = Some((id, x));
+        }
+    }
+
+    filled
+}
+
+///
+/// Remove groups of fewer than `threshold` connected pixels that share a segment. Returns the number of removed
+/// pixels for each segment
+///
+#[allow(clippy::diverging_sub_expression)]
+pub fn drop_islands(
+    pixels_to_mask_id: &mut [Option<u16>],
+    threshold: usize,
+) -> BTreeMap<u16, usize> {
+    let mut visited = vec![false; WIDTH * HEIGHT];
+    let mut removed: BTreeMap<u16, usize> = BTreeMap::new();
+
+    for start in 0..WIDTH * HEIGHT {
+        guard!(let Some(id) = pixels_to_mask_id[start] else {
+            continue;
+        });
+
+        if visited[start] {
+            continue;
+        }
+
+        // Flood fill the 4-connected group of pixels with this ID
+        let mut island: Vec<usize> = vec![];
+        let mut stack = vec![start];
+        visited[start] = true;
+
+        while let Some(i) = stack.pop() {
+            island.push(i);
+
+            let (x, y) = (i % WIDTH, i / WIDTH);
+
+            let mut neighbors = Vec::with_capacity(4);
+            if x > 0 {
+                neighbors.push(i - 1);
+            }
+            if x + 1 < WIDTH {
+                neighbors.push(i + 1);
+            }
+            if y > 0 {
+                neighbors.push(i - WIDTH);
+            }
+            if y + 1 < HEIGHT {
+                neighbors.push(i + WIDTH);
+            }
+
+            for neighbor in neighbors {
+                if !visited[neighbor] && pixels_to_mask_id[neighbor] == Some(id) {
+                    visited[neighbor] = true;
+                    stack.push(neighbor);
+                }
+            }
+        }
+
+        if island.len() < threshold {
+            for i in &island {
+                pixels_to_mask_id[*i] = None;
+            }
+
+            *removed.entry(id).or_default() += island.len();
+        }
+    }
+
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty() -> Vec<Option<u16>> {
+        vec![None; WIDTH * HEIGHT]
+    }
+
+    fn fill(pixels: &mut [Option<u16>], y: usize, xs: std::ops::Range<usize>, id: u16) {
+        for x in xs {
+            pixels[y * WIDTH + x] = Some(id);
+        }
+    }
+
+    #[test]
+    fn count_runs_per_row_and_segment() {
+        let mut pixels = empty();
+        assert_eq!(count_runs(&pixels), 0);
+
+        // Two segments touching, then a gap, then the first segment again
+        fill(&mut pixels, 0, 0..4, 1);
+        fill(&mut pixels, 0, 4..6, 2);
+        fill(&mut pixels, 0, 8..10, 1);
+        // Runs don't continue across rows
+        fill(&mut pixels, 0, WIDTH - 2..WIDTH, 3);
+        fill(&mut pixels, 1, 0..2, 3);
+
+        assert_eq!(count_runs(&pixels), 5);
+    }
+
+    #[test]
+    fn merge_gaps_fills_small_gaps_in_a_segment() {
+        let mut pixels = empty();
+        fill(&mut pixels, 2, 0..3, 1);
+        fill(&mut pixels, 2, 5..8, 1);
+        // Too wide to fill
+        fill(&mut pixels, 2, 12..14, 1);
+        // A different segment isn't merged with
+        fill(&mut pixels, 3, 0..2, 1);
+        fill(&mut pixels, 3, 3..5, 2);
+
+        let filled = merge_gaps(&mut pixels, 2);
+        let row = 2 * WIDTH;
+
+        assert_eq!(filled, vec![(row + 3, row + 2), (row + 4, row + 2)]);
+        assert_eq!(count_runs(&pixels), 4);
+        assert_eq!(pixels[row + 10], None);
+    }
+
+    #[test]
+    fn drop_islands_removes_small_groups() {
+        let mut pixels = empty();
+        // Large enough to keep
+        fill(&mut pixels, 0, 0..2, 1);
+        fill(&mut pixels, 1, 0..2, 1);
+        // Diagonal neighbors aren't connected
+        fill(&mut pixels, 2, 2..3, 1);
+        // Touching, but a different segment
+        fill(&mut pixels, 0, 2..3, 2);
+        fill(&mut pixels, 10, 10..12, 2);
+
+        let removed = drop_islands(&mut pixels, 3);
+
+        assert_eq!(removed, BTreeMap::from([(1, 1), (2, 3)]));
+        assert_eq!(count_runs(&pixels), 2);
+        assert_eq!(pixels[2 * WIDTH + 2], None);
+        assert_eq!(pixels[0], Some(1));
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde_json::json;
+
+use crate::manifest::PlatformSpecification;
+
+/// The folder the core browses for games, matching its OSD name
+const GAMES_FOLDER: &str = "Game and Watch";
+/// The core's `FS0` file slot
+const GAME_FILE_INDEX: u32 = 0;
+
+///
+/// The MiSTer (and MiMiC) SD card layout for the core
+///
+pub struct MisterLayout {
+    /// The core's RBF, relative to the SD card root and without the date and extension
+    rbf: String,
+}
+
+pub struct GameListEntry {
+    pub key: String,
+    pub title: String,
+    pub company: String,
+    pub year: String,
+    pub cpu: String,
+    /// Relative to the games directory
+    pub file: PathBuf,
+    /// Relative to the SD card root
+    pub launcher: PathBuf,
+}
+
+impl GameListEntry {
+    pub fn new(
+        platform_name: &str,
+        platform: &PlatformSpecification,
+        file: &Path,
+        launcher: &Path,
+    ) -> Self {
+        GameListEntry {
+            key: platform_name.to_string(),
+            title: platform.metadata.name.clone(),
+            company: platform.metadata.company.clone(),
+            year: platform.metadata.year.clone(),
+            cpu: format!("{:?}", platform.device.cpu),
+            file: file.to_path_buf(),
+            launcher: launcher.to_path_buf(),
+        }
+    }
+}
+
+/// Join path components with forward slashes, as MiSTer expects on every host
+fn forward_slash_path(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+impl MisterLayout {
+    pub fn new(rbf: &str) -> Self {
+        MisterLayout {
+            rbf: rbf.to_string(),
+        }
+    }
+
+    ///
+    /// The directory the core loads games from, `games/Game and Watch`
+    ///
+    pub fn games_dir(&self, sd_root: &Path) -> PathBuf {
+        sd_root.join("games").join(GAMES_FOLDER)
+    }
+
+    ///
+    /// Write an MGL launcher for a game, so it has its own entry in the MiSTer menu. `game_path` is the .gnw path
+    /// relative to the games directory. The launcher is placed in `_Console/_Game and Watch`, mirroring any
+    /// subfolders, which are prefixed with `_` so the menu shows them
+    ///
+    #[allow(clippy::diverging_sub_expression)]
+    pub fn write_mgl(&self, sd_root: &Path, game_path: &Path) -> Result<PathBuf, String> {
+        let mut launcher_path = PathBuf::from("_Console").join(format!("_{GAMES_FOLDER}"));
+
+        if let Some(parent) = game_path.parent() {
+            for component in parent.components() {
+                launcher_path.push(format!("_{}", component.as_os_str().to_string_lossy()));
+            }
+        }
+
+        guard!(let Some(file_name) = game_path.file_name() else {
+            return Err(format!("Game path {game_path:?} has no file name"));
+        });
+
+        launcher_path.push(file_name);
+        launcher_path.set_extension("mgl");
+
+        // The file path is relative to the core's games directory
+        let mgl = format!(
+            "<mistergamedescription>\n  <rbf>{}</rbf>\n  <file delay=\"1\" type=\"f\" index=\"{GAME_FILE_INDEX}\" path=\"{}\"/>\n</mistergamedescription>\n",
+            escape_xml(&self.rbf),
+            escape_xml(&forward_slash_path(game_path))
+        );
+
+        let output_path = sd_root.join(&launcher_path);
+
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| format!("Could not create directory {parent:?}: {err}"))?;
+        }
+
+        fs::write(&output_path, mgl)
+            .map_err(|err| format!("Could not write {output_path:?}: {err}"))?;
+
+        Ok(launcher_path)
+    }
+
+    ///
+    /// Write `gamelist.json` to the games directory, listing every generated game with its metadata and launcher
+    ///
+    pub fn write_game_list(
+        &self,
+        sd_root: &Path,
+        entries: &[GameListEntry],
+    ) -> Result<PathBuf, String> {
+        let games = entries
+            .iter()
+            .map(|entry| {
+                json!({
+                    "key": entry.key,
+                    "title": entry.title,
+                    "company": entry.company,
+                    "year": entry.year,
+                    "cpu": entry.cpu,
+                    "file": forward_slash_path(&entry.file),
+                    "launcher": forward_slash_path(&entry.launcher),
+                })
+            })
+            .collect::<Vec<serde_json::Value>>();
+
+        let output_path = self.games_dir(sd_root).join("gamelist.json");
+
+        fs::create_dir_all(self.games_dir(sd_root))
+            .map_err(|err| format!("Could not create games directory: {err}"))?;
+
+        fs::write(
+            &output_path,
+            serde_json::to_string_pretty(&json!({ "games": games })).unwrap(),
+        )
+        .map_err(|err| format!("Could not write {output_path:?}: {err}"))?;
+
+        Ok(output_path)
+    }
+}
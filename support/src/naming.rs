@@ -0,0 +1,210 @@
+use std::path::PathBuf;
+
+use crate::manifest::PlatformSpecification;
+
+#[derive(Clone, Debug)]
+pub enum OutputGrouping {
+    Company,
+    Cpu,
+    Year,
+}
+
+///
+/// Builds the output path of each game from a template, such as `{name} ({year})`
+///
+#[derive(Clone, Debug)]
+pub struct OutputNaming {
+    template: String,
+    grouping: Option<OutputGrouping>,
+}
+
+const PLACEHOLDERS: [&str; 5] = ["key", "name", "company", "year", "cpu"];
+
+/// Names that FAT32 and exFAT (via Windows) refuse, regardless of extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+impl OutputNaming {
+    #[allow(clippy::diverging_sub_expression)]
+    pub fn new(template: &str, grouping: Option<OutputGrouping>) -> Result<Self, String> {
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            guard!(let Some(length) = rest[start..].find('}') else {
+                return Err(format!("Unclosed placeholder in naming template \"{template}\""));
+            });
+
+            let placeholder = &rest[start + 1..start + length];
+
+            if !PLACEHOLDERS.contains(&placeholder) {
+                return Err(format!(
+                    "Unknown placeholder {{{placeholder}}} in naming template \"{template}\". Expected one of {}",
+                    PLACEHOLDERS
+                        .iter()
+                        .map(|p| format!("{{{p}}}"))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                ));
+            }
+
+            rest = &rest[start + length + 1..];
+        }
+
+        Ok(OutputNaming {
+            template: template.to_string(),
+            grouping,
+        })
+    }
+
+    ///
+    /// The path of the game's .gnw file, relative to the output directory
+    ///
+    pub fn relative_path(&self, platform_name: &str, platform: &PlatformSpecification) -> PathBuf {
+        let cpu = format!("{:?}", platform.device.cpu);
+
+        let mut file_name = self.template.clone();
+
+        for (placeholder, value) in [
+            ("key", platform_name),
+            ("name", &display_name(&platform.metadata.name)),
+            ("company", &platform.metadata.company),
+            ("year", &platform.metadata.year),
+            ("cpu", &cpu),
+        ] {
+            file_name = file_name.replace(&format!("{{{placeholder}}}"), value);
+        }
+
+        let file_name = format!("{}.gnw", sanitize_file_name(&file_name));
+
+        let group = match self.grouping {
+            Some(OutputGrouping::Company) => Some(&platform.metadata.company),
+            Some(OutputGrouping::Cpu) => Some(&cpu),
+            Some(OutputGrouping::Year) => Some(&platform.metadata.year),
+            None => None,
+        };
+
+        match group {
+            Some(group) => PathBuf::from(sanitize_file_name(group)).join(file_name),
+            None => PathBuf::from(file_name),
+        }
+    }
+}
+
+///
+/// The game's title, without the "Game & Watch:" prefix shared by most of the library
+///
+fn display_name(name: &str) -> String {
+    let prefix = "game & watch:";
+
+    let name = if name.to_lowercase().starts_with(prefix) {
+        &name[prefix.len()..]
+    } else {
+        name
+    };
+
+    name.replace(':', " -").trim().to_string()
+}
+
+///
+/// Make a name safe for FAT32 and exFAT SD cards, replacing illegal characters, trailing dots and spaces, and
+/// reserved device names
+///
+pub fn sanitize_file_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| match c {
+            '"' | '*' | '/' | ':' | '<' | '>' | '?' | '\\' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+
+    let name = name.trim().trim_end_matches(['.', ' ']).to_string();
+
+    let stem = name.split('.').next().unwrap_or("");
+
+    if name.is_empty() {
+        "_".to_string()
+    } else if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem.trim_end()))
+    {
+        format!("_{name}")
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn platform(name: &str, company: &str) -> PlatformSpecification {
+        serde_json::from_value(serde_json::json!({
+            "device": {
+                "cpu": "sm510",
+                "screen": { "type": "single", "width": 160, "height": 80 }
+            },
+            "portMap": { "ports": [] },
+            "metadata": { "year": "198?", "name": name, "company": company },
+            "rom": { "rom": "test.bin", "romHash": "" }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn sanitize_replaces_illegal_characters() {
+        assert_eq!(
+            sanitize_file_name("a/b\\c:d*e?f\"g<h>i|j"),
+            "a_b_c_d_e_f_g_h_i_j"
+        );
+        assert_eq!(sanitize_file_name("tab\there"), "tab_here");
+    }
+
+    #[test]
+    fn sanitize_trims_trailing_dots_and_spaces() {
+        assert_eq!(sanitize_file_name("  Game. . "), "Game");
+        assert_eq!(sanitize_file_name("..."), "_");
+        assert_eq!(sanitize_file_name(""), "_");
+    }
+
+    #[test]
+    fn sanitize_escapes_reserved_names() {
+        assert_eq!(sanitize_file_name("CON"), "_CON");
+        assert_eq!(sanitize_file_name("com1.txt"), "_com1.txt");
+        assert_eq!(sanitize_file_name("Console"), "Console");
+    }
+
+    #[test]
+    fn template_rejects_bad_placeholders() {
+        assert!(OutputNaming::new("{key", None).is_err());
+        assert!(OutputNaming::new("{title}", None).is_err());
+        assert!(OutputNaming::new("{name} ({year}) [{cpu}]", None).is_ok());
+    }
+
+    #[test]
+    fn template_fills_placeholders() {
+        let naming = OutputNaming::new("{name} ({company}, {year}) {cpu} {key}", None).unwrap();
+        let platform = platform("Game & Watch: Donkey Kong: Jr", "Nintendo");
+
+        assert_eq!(
+            naming.relative_path("gnw_dkjr", &platform),
+            Path::new("Donkey Kong - Jr (Nintendo, 198_) SM510 gnw_dkjr.gnw")
+        );
+    }
+
+    #[test]
+    fn template_groups_into_sanitized_folder() {
+        let naming = OutputNaming::new("{key}", Some(OutputGrouping::Company)).unwrap();
+        let platform = platform("Test", "bootleg: Elektronika");
+
+        assert_eq!(
+            naming.relative_path("gnw_test", &platform),
+            Path::new("bootleg_ Elektronika").join("gnw_test.gnw")
+        );
+    }
+}
@@ -0,0 +1,577 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use colored::Colorize;
+use sha1::{Digest, Sha1};
+
+use crate::manifest::{PlatformSpecification, RomPatch};
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+/// Source, target, and patch CRC32s
+const BPS_FOOTER_LENGTH: usize = 12;
+/// Far larger than any supported ROM. Sizes read from a patch are checked against this before anything is allocated
+const MAX_PATCHED_SIZE: usize = 0x100_0000;
+
+///
+/// Resolve relative manifest patch paths against the manifest's directory, then apply the `--patch` and
+/// `--patched-hash` overrides
+///
+#[allow(clippy::diverging_sub_expression)]
+pub fn apply_patch_args(
+    manifest: &mut HashMap<String, PlatformSpecification>,
+    manifest_dir: &Path,
+    patches: &[(String, PathBuf)],
+    patched_hashes: &[(String, String)],
+) -> Result<(), String> {
+    for platform in manifest.values_mut() {
+        if let Some(patch) = &mut platform.rom.patch {
+            patch.file = manifest_dir.join(&patch.file);
+        }
+    }
+
+    for (name, file) in patches {
+        guard!(let Some(platform) = manifest.get_mut(name) else {
+            return Err(format!("Cannot patch {name}: No manifest entry found"));
+        });
+
+        platform.rom.patch = Some(RomPatch {
+            file: file.clone(),
+            patched_hash: None,
+        });
+    }
+
+    for (name, hash) in patched_hashes {
+        guard!(let Some(patch) = manifest.get_mut(name).and_then(|p| p.rom.patch.as_mut()) else {
+            return Err(format!("Cannot set the patched SHA-1 of {name}: It has no patch"));
+        });
+
+        patch.patched_hash = Some(hash.clone());
+    }
+
+    Ok(())
+}
+
+///
+/// Apply an IPS or BPS patch to a ROM that has already been verified against the manifest, then verify the result
+/// against the patch's expected SHA-1. If `allow_mismatch` is set, a missing or mismatched SHA-1 is only a warning
+///
+pub fn patch_rom(rom: &[u8], patch: &RomPatch, allow_mismatch: bool) -> Result<Vec<u8>, String> {
+    let file = &patch.file;
+    let data = fs::read(file).map_err(|err| format!("Could not read patch {file:?}: {err}"))?;
+
+    let patched = if data.starts_with(IPS_MAGIC) {
+        apply_ips(rom, &data)
+    } else if data.starts_with(BPS_MAGIC) {
+        apply_bps(rom, &data)
+    } else {
+        Err("Unknown format. Expected an IPS or BPS patch".to_string())
+    }
+    .map_err(|err| format!("Could not apply patch {file:?}: {err}"))?;
+
+    let actual_hash = hex::encode(Sha1::digest(&patched));
+
+    let message = match &patch.patched_hash {
+        Some(expected_hash) if expected_hash.to_lowercase() == actual_hash => return Ok(patched),
+        Some(expected_hash) => format!(
+            "Patched ROM SHA-1 mismatch\nExpected: {}\nActual:   {actual_hash}",
+            expected_hash.to_lowercase()
+        ),
+        None => format!("No patched SHA-1 provided. Patched ROM has SHA-1 {actual_hash}"),
+    };
+
+    if allow_mismatch {
+        println!(
+            "{}",
+            format!("{message}\nUsing unverified patched ROM").yellow()
+        );
+
+        Ok(patched)
+    } else {
+        Err(format!(
+            "{message}\nSet the patch's `patchedHash` or `--patched-hash`, or use `--allow-patched-rom-mismatch` to use it anyway"
+        ))
+    }
+}
+
+#[allow(clippy::diverging_sub_expression)]
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let mut output = rom.to_vec();
+    let mut position = IPS_MAGIC.len();
+
+    let mut take = |length: usize| -> Result<&[u8], String> {
+        guard!(let Some(slice) = position.checked_add(length).and_then(|end| patch.get(position..end)) else {
+            return Err("Patch is truncated".to_string());
+        });
+
+        position += length;
+
+        Ok(slice)
+    };
+
+    let read_int = |bytes: &[u8]| bytes.iter().fold(0, |value, b| (value << 8) | *b as usize);
+
+    loop {
+        let offset = take(3)?;
+
+        if offset == IPS_EOF {
+            break;
+        }
+
+        let offset = read_int(offset);
+        let size = read_int(take(2)?);
+
+        // A zero size marks a run length encoded record
+        let record = if size == 0 {
+            let count = read_int(take(2)?);
+            vec![take(1)?[0]; count]
+        } else {
+            take(size)?.to_vec()
+        };
+
+        if output.len() < offset + record.len() {
+            output.resize(offset + record.len(), 0);
+        }
+
+        output[offset..offset + record.len()].copy_from_slice(&record);
+    }
+
+    // Some patches truncate the output to a length stored after the EOF marker
+    if let Ok(length) = take(3) {
+        output.truncate(read_int(length));
+    }
+
+    Ok(output)
+}
+
+#[allow(clippy::diverging_sub_expression)]
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < BPS_MAGIC.len() + BPS_FOOTER_LENGTH {
+        return Err("Patch is truncated".to_string());
+    }
+
+    let footer_start = patch.len() - BPS_FOOTER_LENGTH;
+    let read_crc =
+        |offset: usize| u32::from_le_bytes(patch[offset..offset + 4].try_into().unwrap());
+
+    if crc32(&patch[..patch.len() - 4]) != read_crc(footer_start + 8) {
+        return Err("Patch checksum mismatch. The patch is corrupted".to_string());
+    }
+
+    if crc32(rom) != read_crc(footer_start) {
+        return Err("Patch was made for a different ROM".to_string());
+    }
+
+    let mut reader = BpsReader {
+        data: &patch[..footer_start],
+        position: BPS_MAGIC.len(),
+    };
+
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+
+    if target_size > MAX_PATCHED_SIZE {
+        return Err(format!(
+            "Patch produces a {target_size} byte ROM, larger than the {MAX_PATCHED_SIZE} byte limit"
+        ));
+    }
+
+    if source_size != rom.len() {
+        return Err(format!(
+            "Patch expects a {source_size} byte ROM, but the ROM is {} bytes",
+            rom.len()
+        ));
+    }
+
+    reader.take(metadata_size)?;
+
+    let mut output = Vec::with_capacity(target_size);
+    let mut source_offset = 0;
+    let mut target_offset = 0;
+
+    while !reader.is_empty() {
+        let action = reader.number()?;
+        let length = (action >> 2) + 1;
+
+        // Checked up front, so a bad patch can't grow the output without bound
+        if length > target_size - output.len() {
+            return Err("Patch writes past the end of the patched ROM".to_string());
+        }
+
+        match action & 0x3 {
+            // Source read. Copy from the same offset in the source
+            0 => {
+                let start = output.len();
+
+                guard!(let Some(bytes) = rom.get(start..start + length) else {
+                    return Err("Patch reads past the end of the ROM".to_string());
+                });
+
+                output.extend_from_slice(bytes);
+            }
+            // Target read. Copy from the patch
+            1 => output.extend_from_slice(reader.take(length)?),
+            // Source copy. Copy from a relative offset in the source
+            2 => {
+                source_offset = reader.relative_offset(source_offset)?;
+
+                guard!(let Some(bytes) = source_offset.checked_add(length).and_then(|end| rom.get(source_offset..end)) else {
+                    return Err("Patch reads past the end of the ROM".to_string());
+                });
+
+                output.extend_from_slice(bytes);
+                source_offset += length;
+            }
+            // Target copy. Copy from a relative offset in the output, which may overlap the bytes being written
+            _ => {
+                target_offset = reader.relative_offset(target_offset)?;
+
+                for _ in 0..length {
+                    guard!(let Some(byte) = output.get(target_offset).copied() else {
+                        return Err("Patch reads past the end of the output".to_string());
+                    });
+
+                    output.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if output.len() != target_size {
+        return Err(format!(
+            "Patch produced {} bytes, but expected {target_size}",
+            output.len()
+        ));
+    }
+
+    if crc32(&output) != read_crc(footer_start + 4) {
+        return Err("Patched ROM checksum mismatch".to_string());
+    }
+
+    Ok(output)
+}
+
+struct BpsReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BpsReader<'a> {
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    #[allow(clippy::diverging_sub_expression)]
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let end = self.position.checked_add(length);
+
+        guard!(let Some(slice) = end.and_then(|end| self.data.get(self.position..end)) else {
+            return Err("Patch is truncated".to_string());
+        });
+
+        self.position += length;
+
+        Ok(slice)
+    }
+
+    ///
+    /// A variable length number. Each byte holds 7 bits, and the high bit marks the last byte
+    ///
+    fn number(&mut self) -> Result<usize, String> {
+        let too_large = || "Patch contains a number that is too large".to_string();
+
+        let mut value = 0usize;
+        let mut shift = 1usize;
+
+        loop {
+            let byte = self.take(1)?[0] as usize;
+
+            value = (byte & 0x7F)
+                .checked_mul(shift)
+                .and_then(|digit| value.checked_add(digit))
+                .ok_or_else(too_large)?;
+
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+
+            shift = shift.checked_mul(0x80).ok_or_else(too_large)?;
+            value = value.checked_add(shift).ok_or_else(too_large)?;
+        }
+    }
+
+    ///
+    /// A copy offset, stored as a signed distance from the previous offset
+    ///
+    fn relative_offset(&mut self, offset: usize) -> Result<usize, String> {
+        let value = self.number()?;
+        let distance = value >> 1;
+
+        let offset = if value & 1 != 0 {
+            offset.checked_sub(distance)
+        } else {
+            offset.checked_add(distance)
+        };
+
+        offset.ok_or_else(|| "Patch copies from before the start of the data".to_string())
+    }
+}
+
+///
+/// CRC-32 (IEEE), as used by BPS
+///
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bps_number(mut value: usize) -> Vec<u8> {
+        let mut bytes = vec![];
+
+        loop {
+            let digit = (value & 0x7F) as u8;
+            value >>= 7;
+
+            if value == 0 {
+                bytes.push(digit | 0x80);
+                return bytes;
+            }
+
+            bytes.push(digit);
+            value -= 1;
+        }
+    }
+
+    fn bps_action(kind: usize, length: usize) -> Vec<u8> {
+        bps_number(((length - 1) << 2) | kind)
+    }
+
+    fn build_bps(source: &[u8], target: &[u8], target_size: usize, actions: &[u8]) -> Vec<u8> {
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(bps_number(source.len()));
+        patch.extend(bps_number(target_size));
+        patch.extend(bps_number(0));
+        patch.extend_from_slice(actions);
+        patch.extend(crc32(source).to_le_bytes());
+        patch.extend(crc32(target).to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+
+        patch
+    }
+
+    fn source() -> Vec<u8> {
+        (0..16).collect()
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn bps_number_round_trip() {
+        for value in [
+            0,
+            1,
+            0x7F,
+            0x80,
+            0x407F,
+            0x4080,
+            0x12_3456,
+            MAX_PATCHED_SIZE,
+        ] {
+            let bytes = bps_number(value);
+            let mut reader = BpsReader {
+                data: &bytes,
+                position: 0,
+            };
+
+            assert_eq!(reader.number(), Ok(value));
+            assert!(reader.is_empty());
+        }
+    }
+
+    #[test]
+    fn bps_number_overflow() {
+        let bytes = [0x7F; 16];
+        let mut reader = BpsReader {
+            data: &bytes,
+            position: 0,
+        };
+
+        assert_eq!(
+            reader.number(),
+            Err("Patch contains a number that is too large".to_string())
+        );
+    }
+
+    #[test]
+    fn bps_applies_every_action() {
+        let source = source();
+
+        let mut target = source[0..4].to_vec();
+        target.extend_from_slice(b"XYZ");
+        target.extend_from_slice(&source[8..12]);
+        // Overlapping target copy, repeating the last byte
+        target.extend_from_slice(&[11; 3]);
+
+        let mut actions = bps_action(0, 4);
+        actions.extend(bps_action(1, 3));
+        actions.extend_from_slice(b"XYZ");
+        actions.extend(bps_action(2, 4));
+        actions.extend(bps_number(8 << 1));
+        actions.extend(bps_action(3, 3));
+        actions.extend(bps_number(10 << 1));
+
+        let patch = build_bps(&source, &target, target.len(), &actions);
+
+        assert_eq!(apply_bps(&source, &patch), Ok(target));
+    }
+
+    #[test]
+    fn bps_rejects_wrong_source() {
+        let source = source();
+        let patch = build_bps(&source, &source, source.len(), &bps_action(0, 16));
+
+        assert_eq!(apply_bps(&source, &patch), Ok(source.clone()));
+        assert_eq!(
+            apply_bps(&source[1..], &patch),
+            Err("Patch was made for a different ROM".to_string())
+        );
+    }
+
+    #[test]
+    fn bps_rejects_corrupted_patch() {
+        let source = source();
+        let mut patch = build_bps(&source, &source, source.len(), &bps_action(0, 16));
+        patch[5] ^= 1;
+
+        assert_eq!(
+            apply_bps(&source, &patch),
+            Err("Patch checksum mismatch. The patch is corrupted".to_string())
+        );
+    }
+
+    #[test]
+    fn bps_rejects_oversized_target() {
+        let source = source();
+        let patch = build_bps(&source, &source, usize::MAX >> 8, &[]);
+
+        assert!(apply_bps(&source, &patch)
+            .unwrap_err()
+            .contains("byte limit"));
+    }
+
+    #[test]
+    fn bps_rejects_writes_past_target() {
+        let source = source();
+        let patch = build_bps(&source, &source[..4], 4, &bps_action(0, 16));
+
+        assert_eq!(
+            apply_bps(&source, &patch),
+            Err("Patch writes past the end of the patched ROM".to_string())
+        );
+    }
+
+    #[test]
+    fn bps_rejects_copies_outside_source() {
+        let source = source();
+
+        let mut actions = bps_action(2, 4);
+        actions.extend(bps_number(14 << 1));
+
+        let patch = build_bps(&source, &source[..4], 4, &actions);
+
+        assert_eq!(
+            apply_bps(&source, &patch),
+            Err("Patch reads past the end of the ROM".to_string())
+        );
+    }
+
+    #[test]
+    fn ips_applies_records() {
+        let mut patch = IPS_MAGIC.to_vec();
+        // Plain record
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x02, 0xAA, 0xBB]);
+        // Run length encoded record
+        patch.extend_from_slice(&[0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x03, 0xCC]);
+        // Record past the end of the ROM
+        patch.extend_from_slice(&[0x00, 0x00, 0x12, 0x00, 0x01, 0xDD]);
+        patch.extend_from_slice(IPS_EOF);
+
+        let mut expected = source();
+        expected[2..4].copy_from_slice(&[0xAA, 0xBB]);
+        expected[8..11].copy_from_slice(&[0xCC; 3]);
+        expected.extend_from_slice(&[0x00, 0x00, 0xDD]);
+
+        assert_eq!(apply_ips(&source(), &patch), Ok(expected));
+    }
+
+    #[test]
+    fn ips_truncates_to_length() {
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(IPS_EOF);
+        patch.extend_from_slice(&[0x00, 0x00, 0x08]);
+
+        assert_eq!(apply_ips(&source(), &patch), Ok(source()[..8].to_vec()));
+    }
+
+    #[test]
+    fn ips_rejects_truncated_patch() {
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x04, 0xAA]);
+
+        assert_eq!(
+            apply_ips(&source(), &patch),
+            Err("Patch is truncated".to_string())
+        );
+    }
+
+    #[test]
+    fn patch_rom_requires_patched_hash() {
+        let file =
+            std::env::temp_dir().join(format!("romgenerator-test-{}.ips", std::process::id()));
+
+        let mut data = IPS_MAGIC.to_vec();
+        data.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x01, 0xFF]);
+        data.extend_from_slice(IPS_EOF);
+        fs::write(&file, data).unwrap();
+
+        let mut expected = source();
+        expected[0] = 0xFF;
+
+        let mut patch = RomPatch {
+            file: file.clone(),
+            patched_hash: None,
+        };
+
+        assert!(patch_rom(&source(), &patch, false).is_err());
+        assert_eq!(patch_rom(&source(), &patch, true), Ok(expected.clone()));
+
+        patch.patched_hash = Some(hex::encode(Sha1::digest(source())));
+        assert!(patch_rom(&source(), &patch, false).is_err());
+
+        patch.patched_hash = Some(hex::encode_upper(Sha1::digest(&expected)));
+        assert_eq!(patch_rom(&source(), &patch, false), Ok(expected));
+
+        fs::remove_file(file).unwrap();
+    }
+}
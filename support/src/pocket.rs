@@ -0,0 +1,157 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use serde_json::json;
+
+// The core's definitions are embedded, so the generated layout always matches the packaged core
+const CORE_JSON: &str = include_str!("../../pkg/pocket/Cores/agg23.GameAndWatch/core.json");
+const DATA_JSON: &str = include_str!("../../pkg/pocket/Cores/agg23.GameAndWatch/data.json");
+
+#[derive(Deserialize)]
+struct CoreFile {
+    core: Core,
+}
+
+#[derive(Deserialize)]
+struct Core {
+    metadata: CoreMetadata,
+}
+
+#[derive(Deserialize)]
+struct CoreMetadata {
+    platform_ids: Vec<String>,
+    shortname: String,
+    author: String,
+}
+
+#[derive(Deserialize)]
+struct DataFile {
+    data: Data,
+}
+
+#[derive(Deserialize)]
+struct Data {
+    data_slots: Vec<DataSlot>,
+}
+
+#[derive(Deserialize)]
+struct DataSlot {
+    id: u32,
+    #[serde(default)]
+    extensions: Vec<String>,
+}
+
+///
+/// The Analogue Pocket SD card layout for the core
+///
+pub struct PocketLayout {
+    /// `gameandwatch`
+    platform_id: String,
+    /// `agg23.GameAndWatch`
+    core_id: String,
+    /// The data slot that loads .gnw files
+    game_slot_id: u32,
+}
+
+impl PocketLayout {
+    #[allow(clippy::diverging_sub_expression)]
+    pub fn new() -> Result<Self, String> {
+        let core: CoreFile = serde_json::from_str(CORE_JSON)
+            .map_err(|err| format!("Could not parse Pocket core.json: {err}"))?;
+        let data: DataFile = serde_json::from_str(DATA_JSON)
+            .map_err(|err| format!("Could not parse Pocket data.json: {err}"))?;
+
+        let metadata = core.core.metadata;
+
+        guard!(let Some(platform_id) = metadata.platform_ids.first() else {
+            return Err("Pocket core.json has no platform ID".to_string());
+        });
+
+        guard!(let Some(game_slot) = data.data.data_slots.iter().find(|slot| {
+            slot.extensions.iter().any(|ext| ext.eq_ignore_ascii_case("gnw"))
+        }) else {
+            return Err("Pocket data.json has no data slot for .gnw files".to_string());
+        });
+
+        Ok(PocketLayout {
+            platform_id: platform_id.clone(),
+            core_id: format!("{}.{}", metadata.author, metadata.shortname),
+            game_slot_id: game_slot.id,
+        })
+    }
+
+    ///
+    /// The directory the core loads game data from, `Assets/gameandwatch/common`
+    ///
+    pub fn common_dir(&self, sd_root: &Path) -> PathBuf {
+        sd_root
+            .join("Assets")
+            .join(&self.platform_id)
+            .join("common")
+    }
+
+    ///
+    /// The directory of the Pocket's library images, `Platforms/_images`
+    ///
+    pub fn images_dir(&self, sd_root: &Path) -> PathBuf {
+        sd_root.join("Platforms").join("_images")
+    }
+
+    ///
+    /// Write an instance JSON for a game, so it is listed individually in the Pocket's menu. `game_path` is the .gnw
+    /// path relative to the common directory, and the instance JSON mirrors it in the core's asset directory
+    ///
+    #[allow(clippy::diverging_sub_expression)]
+    pub fn write_instance_json(&self, sd_root: &Path, game_path: &Path) -> Result<PathBuf, String> {
+        let instance_path = sd_root
+            .join("Assets")
+            .join(&self.platform_id)
+            .join(&self.core_id)
+            .join(game_path)
+            .with_extension("json");
+
+        guard!(let Some(file_name) = game_path.file_name() else {
+            return Err(format!("Game path {game_path:?} has no file name"));
+        });
+
+        // The data path is relative to the common directory, using forward slashes on every host
+        let data_path = game_path
+            .parent()
+            .map(|parent| {
+                parent
+                    .components()
+                    .map(|component| format!("{}/", component.as_os_str().to_string_lossy()))
+                    .collect::<String>()
+            })
+            .unwrap_or_default();
+
+        let instance = json!({
+            "instance": {
+                "magic": "APF_VER_1",
+                "data_path": data_path,
+                "data_slots": [
+                    {
+                        "id": self.game_slot_id,
+                        "filename": file_name.to_string_lossy(),
+                    }
+                ],
+            }
+        });
+
+        if let Some(parent) = instance_path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| format!("Could not create directory {parent:?}: {err}"))?;
+        }
+
+        fs::write(
+            &instance_path,
+            serde_json::to_string_pretty(&instance).unwrap(),
+        )
+        .map_err(|err| format!("Could not write {instance_path:?}: {err}"))?;
+
+        Ok(instance_path)
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+use crate::{
+    encode_format::{encode, EncodeOptions},
+    layout::{
+        BlendType, CompleteBounds, Element, Image, MameLayout, NameElement, NameElementChildren,
+        Screen, View, ViewElement, XYBounds,
+    },
+    manifest::{
+        entry_hash, ElementRuleConfig, Metdata, PlatformPortMapping, PlatformSpecification,
+        PresetDefinition, ROMName,
+    },
+    naming::OutputNaming,
+    render::{self, RenderOptions},
+};
+
+/// The descriptor file in each project folder
+pub const PROJECT_DESCRIPTOR: &str = "game.json";
+
+///
+/// A custom game built from loose files, rather than a MAME ROM and artwork set
+///
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProjectDescriptor {
+    /// Defaults to the project folder's name
+    key: Option<String>,
+    metadata: Metdata,
+    device: PresetDefinition,
+    port_map: PlatformPortMapping,
+    rom: ProjectRom,
+    layout: ProjectLayout,
+    elements: Option<ElementRuleConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProjectRom {
+    /// Relative to the project folder
+    file: String,
+    /// If set, the ROM is checked against this SHA-1
+    hash: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProjectLayout {
+    /// The region of the layout to frame with `--framing view`. Defaults to fitting the backgrounds
+    bounds: Option<XYBounds>,
+    /// Drawn in order, so later backgrounds are drawn on top
+    #[serde(default)]
+    backgrounds: Vec<ProjectBackground>,
+    /// The placement of each screen, in the order of the device's screens
+    screens: Vec<ProjectScreen>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProjectBackground {
+    /// A PNG, relative to the project folder
+    image: String,
+    bounds: XYBounds,
+    blend: Option<BlendType>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProjectScreen {
+    bounds: XYBounds,
+}
+
+pub struct Project {
+    pub key: String,
+    pub dir: PathBuf,
+    pub platform: PlatformSpecification,
+    pub layout_manifest: MameLayout,
+    pub view: View,
+    /// Recorded as the manifest entry hash in the generated file
+    pub descriptor_hash: [u8; 20],
+}
+
+impl From<&XYBounds> for CompleteBounds {
+    fn from(bounds: &XYBounds) -> Self {
+        CompleteBounds {
+            x: Some(bounds.x),
+            y: Some(bounds.y),
+            width: Some(bounds.width),
+            height: Some(bounds.height),
+            xc: None,
+            yc: None,
+            left: None,
+            right: None,
+            top: None,
+            bottom: None,
+        }
+    }
+}
+
+///
+/// Load a project from its folder, or from the path of its `game.json`. The layout is converted into the same form as
+/// a parsed MAME layout, so the project can be rendered like any other game
+///
+#[allow(clippy::diverging_sub_expression)]
+pub fn load_project(path: &Path) -> Result<Project, String> {
+    let dir = project_dir(path);
+    let descriptor_path = if path.is_dir() {
+        path.join(PROJECT_DESCRIPTOR)
+    } else {
+        path.to_path_buf()
+    };
+
+    let file = fs::read(&descriptor_path)
+        .map_err(|err| format!("Could not read project descriptor {descriptor_path:?}: {err}"))?;
+
+    let value: serde_json::Value = serde_json::from_slice(&file)
+        .map_err(|err| format!("Could not parse project descriptor {descriptor_path:?}: {err}"))?;
+
+    let descriptor: ProjectDescriptor = serde_json::from_value(value.clone())
+        .map_err(|err| format!("Could not parse project descriptor {descriptor_path:?}: {err}"))?;
+
+    let key = match &descriptor.key {
+        Some(key) => key.clone(),
+        None => dir
+            .canonicalize()
+            .ok()
+            .and_then(|dir| {
+                dir.file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+            })
+            .ok_or_else(|| format!("Could not name project at {dir:?}. Set its `key`"))?,
+    };
+
+    let screen_count = render::screen_suffixes(&descriptor.device.screen).len();
+
+    if descriptor.layout.screens.len() != screen_count {
+        return Err(format!(
+            "Project layout places {} screen(s), but the device has {screen_count}",
+            descriptor.layout.screens.len()
+        ));
+    }
+
+    let mut elements = vec![];
+    let mut items = vec![];
+
+    if let Some(bounds) = &descriptor.layout.bounds {
+        items.push(ViewElement::Bounds(bounds.into()));
+    }
+
+    for background in &descriptor.layout.backgrounds {
+        // Elements are loaded as `[name].png` from the asset directory
+        guard!(let Some(name) = background.image.strip_suffix(".png") else {
+            return Err(format!("Background {} must be a PNG", background.image));
+        });
+
+        elements.push(NameElement {
+            name: name.to_string(),
+            items: vec![NameElementChildren::Image(Image {})],
+        });
+
+        items.push(ViewElement::Element(Element {
+            ref_name: name.to_string(),
+            bounds: (&background.bounds).into(),
+            blend: background.blend.clone(),
+        }));
+    }
+
+    for (index, screen) in descriptor.layout.screens.iter().enumerate() {
+        items.push(ViewElement::Screen(Screen {
+            index: Some(index as i32),
+            tag: None,
+            bounds: (&screen.bounds).into(),
+            blend: None,
+        }));
+    }
+
+    Ok(Project {
+        descriptor_hash: entry_hash(&value),
+        platform: PlatformSpecification {
+            device: descriptor.device,
+            port_map: descriptor.port_map,
+            metadata: descriptor.metadata,
+            rom: ROMName {
+                rom: descriptor.rom.file,
+                melody: None,
+                rom_owner: None,
+                rom_hash: descriptor.rom.hash.unwrap_or_default(),
+                patch: None,
+            },
+            elements: descriptor.elements,
+        },
+        layout_manifest: MameLayout {
+            element: elements,
+            view: vec![],
+        },
+        view: View {
+            name: key.clone(),
+            items,
+        },
+        key,
+        dir,
+    })
+}
+
+///
+/// The folder of a project, given either the folder or its `game.json`
+///
+pub fn project_dir(path: &Path) -> PathBuf {
+    if path.is_dir() {
+        path.to_path_buf()
+    } else {
+        path.parent().unwrap_or(Path::new("")).to_path_buf()
+    }
+}
+
+impl Project {
+    pub fn output_path(&self, output_dir: &Path, naming: &OutputNaming) -> PathBuf {
+        output_dir.join(naming.relative_path(&self.key, &self.platform))
+    }
+
+    ///
+    /// Read the project's ROM, checking it against the descriptor's SHA-1 if one is set
+    ///
+    pub fn load_rom(&self) -> Result<Vec<u8>, String> {
+        let rom_path = self.dir.join(&self.platform.rom.rom);
+
+        let data =
+            fs::read(&rom_path).map_err(|err| format!("Could not read ROM {rom_path:?}: {err}"))?;
+
+        let expected_hash = self.platform.rom.rom_hash.to_lowercase();
+        let actual_hash = hex::encode(Sha1::digest(&data));
+
+        if !expected_hash.is_empty() && expected_hash != actual_hash {
+            return Err(format!(
+                "ROM {} SHA-1 mismatch\nExpected: {expected_hash}\nActual:   {actual_hash}",
+                self.platform.rom.rom
+            ));
+        }
+
+        Ok(data)
+    }
+}
+
+///
+/// Render and encode a project, writing it to the output directory. Returns the path of the generated file
+///
+pub fn generate_project(
+    project: &Project,
+    output_dir: &Path,
+    naming: &OutputNaming,
+    render_options: &RenderOptions,
+    encode_options: &EncodeOptions,
+) -> Result<PathBuf, String> {
+    let rendered_data = render::render(
+        &project.key,
+        &project.view,
+        &project.layout_manifest,
+        &project.platform,
+        &project.dir,
+        render_options,
+    )?;
+
+    let rom_data = project.load_rom()?;
+
+    let output_path = project.output_path(output_dir, naming);
+
+    encode(
+        &project.key,
+        &rendered_data,
+        &project.platform,
+        &project.descriptor_hash,
+        rom_data,
+        &output_path,
+        encode_options,
+    )?;
+
+    Ok(output_path)
+}
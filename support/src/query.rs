@@ -0,0 +1,254 @@
+use std::ops::RangeInclusive;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::{
+    manifest::{CPUType, PlatformSpecification, Screen},
+    CompanyArg, ScreenTypeArg,
+};
+
+///
+/// Composable manifest filters. Each populated filter must match, and within a filter, any of the values can match
+///
+#[derive(Debug, Default)]
+pub struct PlatformQuery {
+    pub companies: Vec<CompanyArg>,
+    pub cpus: Vec<CPUType>,
+    pub screens: Vec<ScreenTypeArg>,
+    pub min_year: Option<u16>,
+    pub max_year: Option<u16>,
+    /// Matched against both the manifest key and the game's title
+    pub name: Option<Regex>,
+    /// Manifest keys to skip
+    pub exclude: Vec<String>,
+    pub exclude_companies: Vec<CompanyArg>,
+}
+
+impl CompanyArg {
+    fn names(&self) -> &'static [&'static str] {
+        match self {
+            CompanyArg::Nintendo => &["nintendo"],
+            CompanyArg::Elektronika => &["elektronika"],
+            CompanyArg::Konami => &["konami"],
+            CompanyArg::Nelsonic => &["nelsonic"],
+            CompanyArg::Tiger => &["tiger", "tiger electronics"],
+            CompanyArg::Tronica => &["tronica"],
+            CompanyArg::VTech => &["vtech"],
+            CompanyArg::Homebrew => &["homebrew"],
+        }
+    }
+
+    ///
+    /// Matches the company exactly, or as the first word (`Tiger Electronics`) or in parentheses
+    /// (`bootleg (Elektronika)`)
+    ///
+    pub fn matches(&self, company: &str) -> bool {
+        let company = company.to_lowercase();
+
+        self.names().iter().any(|name| {
+            company == *name
+                || company.starts_with(&format!("{name} "))
+                || company.contains(&format!("({name})"))
+        })
+    }
+}
+
+impl ScreenTypeArg {
+    pub fn matches(&self, screen: &Screen) -> bool {
+        matches!(
+            (self, screen),
+            (ScreenTypeArg::Single, Screen::Single { .. })
+                | (ScreenTypeArg::DualVertical, Screen::DualVertical { .. })
+                | (ScreenTypeArg::DualHorizontal, Screen::DualHorizontal { .. })
+        )
+    }
+}
+
+///
+/// The range of years a MAME year could be. Unknown digits are written as `?`, so `198?` is 1980 through 1989
+///
+pub fn year_range(year: &str) -> Option<RangeInclusive<u16>> {
+    let year = year.trim();
+
+    if year.len() != 4 || !year.chars().all(|c| c.is_ascii_digit() || c == '?') {
+        return None;
+    }
+
+    let start = year.replace('?', "0").parse::<u16>().ok()?;
+    let end = year.replace('?', "9").parse::<u16>().ok()?;
+
+    Some(start..=end)
+}
+
+pub fn parse_name_regex(pattern: &str) -> Result<Regex, String> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|err| format!("Invalid name regex \"{pattern}\": {err}"))
+}
+
+impl PlatformQuery {
+    #[allow(clippy::diverging_sub_expression)]
+    pub fn matches(&self, key: &str, platform: &PlatformSpecification) -> bool {
+        let company = &platform.metadata.company;
+
+        if !self.companies.is_empty() && !self.companies.iter().any(|c| c.matches(company)) {
+            return false;
+        }
+
+        if self.exclude_companies.iter().any(|c| c.matches(company)) {
+            return false;
+        }
+
+        if !self.cpus.is_empty() && !self.cpus.contains(&platform.device.cpu) {
+            return false;
+        }
+
+        if !self.screens.is_empty()
+            && !self
+                .screens
+                .iter()
+                .any(|s| s.matches(&platform.device.screen))
+        {
+            return false;
+        }
+
+        if self.min_year.is_some() || self.max_year.is_some() {
+            // Games with unparsable years can't be placed in a range, so they never match
+            guard!(let Some(years) = year_range(&platform.metadata.year) else {
+                return false;
+            });
+
+            // Uncertain years match if any of their possible years are in range
+            if self.min_year.is_some_and(|min| *years.end() < min)
+                || self.max_year.is_some_and(|max| *years.start() > max)
+            {
+                return false;
+            }
+        }
+
+        if let Some(name) = &self.name {
+            if !name.is_match(key) && !name.is_match(&platform.metadata.name) {
+                return false;
+            }
+        }
+
+        !self.exclude.iter().any(|exclude| exclude == key)
+    }
+}
+
+fn screen_type_name(screen: &Screen) -> &'static str {
+    match screen {
+        Screen::Single { .. } => "single",
+        Screen::DualVertical { .. } => "dual-vertical",
+        Screen::DualHorizontal { .. } => "dual-horizontal",
+    }
+}
+
+///
+/// Print the platforms as an aligned table, or as a JSON array
+///
+pub fn list(platforms: &[(String, &PlatformSpecification)], json: bool) {
+    let rows = platforms
+        .iter()
+        .map(|(key, platform)| {
+            [
+                key.clone(),
+                platform.metadata.name.clone(),
+                platform.metadata.company.clone(),
+                platform.metadata.year.clone(),
+                format!("{:?}", platform.device.cpu),
+                screen_type_name(&platform.device.screen).to_string(),
+            ]
+        })
+        .collect::<Vec<[String; 6]>>();
+
+    let headers = ["key", "title", "company", "year", "cpu", "screen"];
+
+    if json {
+        let entries = rows
+            .iter()
+            .map(|row| {
+                headers
+                    .iter()
+                    .zip(row.iter())
+                    .map(|(header, value)| (header.to_string(), serde_json::json!(value)))
+                    .collect::<serde_json::Map<String, serde_json::Value>>()
+            })
+            .collect::<Vec<_>>();
+
+        println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+        return;
+    }
+
+    let mut widths = headers.map(|header| header.len());
+
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+
+    let print_row = |values: Vec<&str>| {
+        let line = values
+            .iter()
+            .zip(widths.iter())
+            .map(|(value, width)| format!("{value:width$}"))
+            .collect::<Vec<String>>()
+            .join("  ");
+
+        println!("{}", line.trim_end());
+    };
+
+    let header_row = headers.map(|header| header.to_uppercase());
+    print_row(header_row.iter().map(|header| header.as_str()).collect());
+
+    for row in &rows {
+        print_row(row.iter().map(|value| value.as_str()).collect());
+    }
+
+    println!("-------------------------");
+    println!("Total: {}", rows.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn year_range_exact() {
+        assert_eq!(year_range("1981"), Some(1981..=1981));
+        assert_eq!(year_range(" 1981 "), Some(1981..=1981));
+    }
+
+    #[test]
+    fn year_range_unknown_digits() {
+        assert_eq!(year_range("198?"), Some(1980..=1989));
+        assert_eq!(year_range("19??"), Some(1900..=1999));
+        assert_eq!(year_range("????"), Some(0..=9999));
+    }
+
+    #[test]
+    fn year_range_unparsable() {
+        assert_eq!(year_range(""), None);
+        assert_eq!(year_range("81"), None);
+        assert_eq!(year_range("19811"), None);
+        assert_eq!(year_range("198x"), None);
+    }
+
+    #[test]
+    fn company_matches() {
+        assert!(CompanyArg::Tiger.matches("Tiger Electronics"));
+        assert!(CompanyArg::Tiger.matches("tiger"));
+        assert!(CompanyArg::Elektronika.matches("bootleg (Elektronika)"));
+        assert!(!CompanyArg::Nintendo.matches("Nintendo-like"));
+    }
+
+    #[test]
+    fn name_regex_is_case_insensitive() {
+        let regex = parse_name_regex("donkey").unwrap();
+
+        assert!(regex.is_match("Game & Watch: Donkey Kong"));
+        assert!(parse_name_regex("(").is_err());
+    }
+}
@@ -1,15 +1,24 @@
-use std::{collections::HashSet, path::Path};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use colored::Colorize;
 
 use image::{imageops::FilterType, DynamicImage, ImageBuffer, Rgba};
 use resvg::tiny_skia::{Pixmap, PixmapPaint, PremultipliedColorU8};
 use tiny_skia_path::Transform;
 
 use crate::{
+    element_rules::ElementRules,
     layout::{
         BlendType, Bounds, Element, MameLayout, NameElementChildren, Screen, View, ViewElement,
     },
     manifest::{self, PlatformSpecification, PresetDefinition},
-    svg_manage::build_svg,
+    segment_report::SegmentReport,
+    svg_manage::{
+        assign_segment_id, build_svg, format_segment_id, RenderedSVG, SvgRasterOptions, TitleIssue,
+    },
     HEIGHT, WIDTH,
 };
 
@@ -17,6 +26,70 @@ pub struct RenderedData {
     pub background_bytes: Pixmap,
     pub mask_bytes: Pixmap,
     pub pixels_to_mask_id: Vec<Option<u16>>,
+    /// Pixels covered by more than one segment, with the IDs that lost to the primary ID in `pixels_to_mask_id`
+    pub shared_pixels: BTreeMap<usize, Vec<u16>>,
+    pub segment_report: SegmentReport,
+}
+
+/// The region of the layout that is scaled to fill the output image
+#[derive(Clone, Debug)]
+pub enum Framing {
+    /// The `<bounds>` declared by the view itself. Falls back to `Elements` if the view doesn't declare any
+    View,
+    /// Fit all elements in the view. Screens are excluded, as they sometimes overrun the artwork
+    Elements,
+    /// Fit only the screens, producing an LCD close-up
+    Screens,
+    /// A custom rectangle, in layout coordinates
+    Custom(Bounds),
+}
+
+pub struct RenderOptions {
+    pub framing: Framing,
+    pub element_rules: ElementRules,
+    /// The filter used when resizing element images
+    pub element_filter: FilterType,
+    pub svg: SvgRasterOptions,
+    /// Print the coverage of every segment, rather than just problems
+    pub segment_report: bool,
+    pub debug: bool,
+}
+
+///
+/// Rendered screen SVGs, keyed by path and placement, so rebuilds can skip the SVG ID mapping when only the
+/// backgrounds have changed
+///
+#[derive(Default)]
+pub struct SvgCache {
+    screens: HashMap<PathBuf, (ImageDimensions, RenderedSVG)>,
+}
+
+impl SvgCache {
+    pub fn clear(&mut self) {
+        self.screens.clear();
+    }
+
+    fn get_or_build(
+        &mut self,
+        file_path: &PathBuf,
+        alternate_file_path: &Option<PathBuf>,
+        dimensions: &ImageDimensions,
+        options: &SvgRasterOptions,
+    ) -> Result<&RenderedSVG, String> {
+        let is_cached = self
+            .screens
+            .get(file_path)
+            .is_some_and(|(cached_dimensions, _)| cached_dimensions == dimensions);
+
+        if !is_cached {
+            let rendered_svg = build_svg(file_path, alternate_file_path, dimensions, options)?;
+
+            self.screens
+                .insert(file_path.clone(), (dimensions.clone(), rendered_svg));
+        }
+
+        Ok(&self.screens[file_path].1)
+    }
 }
 
 pub fn render(
@@ -25,14 +98,42 @@ pub fn render(
     layout_manifest: &MameLayout,
     platform: &PlatformSpecification,
     asset_dir: &Path,
-    debug: bool,
+    options: &RenderOptions,
+) -> Result<RenderedData, String> {
+    render_cached(
+        platform_name,
+        layout,
+        layout_manifest,
+        platform,
+        asset_dir,
+        options,
+        &mut SvgCache::default(),
+    )
+}
+
+///
+/// Render, reusing any screen SVGs in the cache that were rendered with the same placement
+///
+#[allow(clippy::diverging_sub_expression)]
+pub fn render_cached(
+    platform_name: &str,
+    layout: &View,
+    layout_manifest: &MameLayout,
+    platform: &PlatformSpecification,
+    asset_dir: &Path,
+    options: &RenderOptions,
+    svg_cache: &mut SvgCache,
 ) -> Result<RenderedData, String> {
-    let mut view_bounds: Option<Bounds> = None;
+    let mut declared_bounds: Option<Bounds> = None;
     let mut elements: Vec<&Element> = vec![];
     let mut screens: Vec<&Screen> = vec![];
 
     let mut filtered_items: Vec<&ViewElement> = vec![];
 
+    let element_rules = options
+        .element_rules
+        .with_game(platform.elements.as_ref())?;
+
     // Keep track of which refs have already been added to the image, as most layouts contain multiple duplicates
     let mut already_applied_refs: HashSet<&String> = HashSet::<&String>::new();
 
@@ -40,14 +141,12 @@ pub fn render(
     for item in &layout.items {
         match item {
             ViewElement::Bounds(bounds) => {
-                // Filter out
-                if view_bounds.is_some() {
-                    return Err(format!(
-                        "View {} in {platform_name} has multiple bounds. Skipping",
-                        layout.name
-                    ));
-                }
-                view_bounds = Some(bounds.to_xy());
+                // Views should only declare one bounds, but if there are several, cover all of them
+                let bounds = bounds.to_xy();
+                declared_bounds = Some(match declared_bounds {
+                    Some(existing) => existing.union(&bounds),
+                    None => bounds,
+                });
             }
             ViewElement::Element(element) | ViewElement::Overlay(element) => {
                 if already_applied_refs.contains(&element.ref_name) {
@@ -56,19 +155,9 @@ pub fn render(
 
                 already_applied_refs.insert(&element.ref_name);
 
-                match element.ref_name.to_lowercase().as_str() {
-                    "dust" | "bubbles" | "unit" | "backdrop" => {
-                        // Ignore these features
-                        println!("Ignoring element by name {}", element.ref_name);
-                        continue;
-                    }
-                    value => {
-                        // if value.starts_with("fix") || value.starts_with("gradient") {
-                        if value.starts_with("gradient") {
-                            println!("Ignoring element by name {}", element.ref_name);
-                            continue;
-                        }
-                    }
+                if let Some(rule) = element_rules.excluded_by(&element.ref_name) {
+                    println!("Ignoring element {} by {rule}", element.ref_name);
+                    continue;
                 }
 
                 filtered_items.push(item);
@@ -81,68 +170,29 @@ pub fn render(
         }
     }
 
-    // Calculate actual bounds
-    let mut min_x: Option<i32> = None;
-    let mut min_y: Option<i32> = None;
-    let mut max_width = 0;
-    let mut max_height = 0;
-    let mut max_common_x: Option<i32> = None;
-    let mut max_common_y: Option<i32> = None;
-
-    // Calculate max bounds
-    // Only Element nodes are used, as Screen's should not drive the overall picture size (they sometimes overrun it)
-    for bounds in elements.iter().map(|e| e.bounds.to_xy()) {
-        if let Some(inner_min_x) = min_x {
-            if inner_min_x > bounds.x {
-                min_x = Some(bounds.x);
-            }
-        }
-
-        if let Some(inner_min_y) = min_y {
-            if inner_min_y > bounds.y {
-                min_y = Some(bounds.y);
-            }
-        }
-
-        if bounds.width + bounds.x > max_width {
-            max_width = bounds.width + bounds.x;
-        }
+    let element_bounds = union_bounds(elements.iter().map(|e| e.bounds.to_xy()));
 
-        if bounds.height + bounds.y > max_height {
-            max_height = bounds.height + bounds.y;
-        }
-
-        if let Some(common_x) = max_common_x {
-            if common_x > bounds.x {
-                // Shorten max common
-                max_common_x = Some(bounds.x);
-            }
-        } else {
-            // Set first value
-            max_common_x = Some(bounds.x);
-        }
+    let view_bounds = match &options.framing {
+        Framing::View => declared_bounds.or(element_bounds),
+        Framing::Elements => element_bounds,
+        Framing::Screens => union_bounds(screens.iter().map(|s| s.bounds.to_xy())),
+        Framing::Custom(bounds) => Some(bounds.clone()),
+    };
 
-        if let Some(common_y) = max_common_y {
-            if common_y > bounds.y {
-                // Shorten max common
-                max_common_y = Some(bounds.y);
-            }
-        } else {
-            // Set first value
-            max_common_y = Some(bounds.y);
-        }
+    guard!(let Some(view_bounds) = view_bounds else {
+        return Err(format!(
+            "View {} in {platform_name} has nothing to frame with {:?}",
+            layout.name, options.framing
+        ));
+    });
+
+    if view_bounds.width <= 0 || view_bounds.height <= 0 {
+        return Err(format!(
+            "View {} in {platform_name} has empty framing bounds {view_bounds:?}",
+            layout.name
+        ));
     }
 
-    let max_common_x = max_common_x.map_or(0, |x| x.max(0));
-    let max_common_y = max_common_y.map_or(0, |y| y.max(0));
-
-    let view_bounds = Bounds {
-        x: (min_x.map_or(0, |x| x) - max_common_x).max(0),
-        y: (min_y.map_or(0, |y| y) - max_common_y).max(0),
-        width: max_width - max_common_x,
-        height: max_height - max_common_y,
-    };
-
     let x_ratio = WIDTH as f32 / view_bounds.width as f32;
     let y_ratio = HEIGHT as f32 / view_bounds.height as f32;
 
@@ -163,12 +213,16 @@ pub fn render(
 
     // Keep track of the set of pixels that make up each screen
     let mut pixels_to_mask_id: Vec<Option<u16>> = vec![None; WIDTH * HEIGHT];
+    let mut shared_pixels: BTreeMap<usize, Vec<u16>> = BTreeMap::new();
+
+    // All segment IDs found across screens, for reporting
+    let mut segment_ids: BTreeSet<u16> = BTreeSet::new();
+    let mut title_issues: Vec<TitleIssue> = vec![];
 
     let mut background_pixmap = Pixmap::new(WIDTH as u32, HEIGHT as u32).unwrap();
     let mut mask_pixmap = Pixmap::new(WIDTH as u32, HEIGHT as u32).unwrap();
 
-    // We currently ignore offsetting by X/Y at the parent view, so the child positions are subtracted
-    // from the parent's offset
+    // All positions are relative to the framed view bounds. Anything outside of them is cropped
     for item in &filtered_items {
         match item {
             ViewElement::Element(element) | ViewElement::Overlay(element) => {
@@ -179,13 +233,7 @@ pub fn render(
                         e.name == element.ref_name
                             && e.items
                                 .iter()
-                                .find(|p| {
-                                    if let NameElementChildren::Image(_) = **p {
-                                        true
-                                    } else {
-                                        false
-                                    }
-                                })
+                                .find(|p| matches!(**p, NameElementChildren::Image(_)))
                                 .is_some()
                     })
                     .is_none()
@@ -212,36 +260,18 @@ pub fn render(
                 )
                 .expect("Could not convert image data");
 
-                let element_bounds = element.bounds.to_xy();
-
-                let x = if element_bounds.x >= 0 {
-                    // Only normalize to 0 if we started out positive
-                    (element_bounds.x - max_common_x).max(0)
-                } else {
-                    element_bounds.x - max_common_x
-                };
-
-                let y = if element_bounds.y >= 0 {
-                    // Only normalize to 0 if we started out positive
-                    (element_bounds.y - max_common_y).max(0)
-                } else {
-                    element_bounds.y - max_common_y
-                };
-
-                let element_bounds = Bounds {
-                    x,
-                    y,
-                    width: element_bounds.width,
-                    height: element_bounds.height,
-                };
-
-                let dimensions =
-                    ImageDimensions::new(&view_bounds, &element_bounds, ratio, x_offset, y_offset);
+                let dimensions = ImageDimensions::new(
+                    &view_bounds,
+                    &element.bounds.to_xy(),
+                    ratio,
+                    x_offset,
+                    y_offset,
+                );
 
                 let image: DynamicImage = DynamicImage::ImageRgba8(image).resize_exact(
                     dimensions.width,
                     dimensions.height,
-                    FilterType::CatmullRom,
+                    options.element_filter,
                 );
 
                 // Dimensions might change by a pixel as part of resizing
@@ -252,7 +282,7 @@ pub fn render(
                     image.into_bytes(),
                     tiny_skia_path::IntSize::from_wh(image_width, image_height).unwrap(),
                 ) else {
-                    return Err(format!("Could not convert PNG into Pixmap"));
+                    return Err("Could not convert PNG into Pixmap".to_string());
                 });
 
                 let mut aligned_image_pixmap = Pixmap::new(WIDTH as u32, HEIGHT as u32).unwrap();
@@ -312,49 +342,41 @@ pub fn render(
                 }
             }
             ViewElement::Screen(screen) => {
+                let index = screen_index(screen, &platform.device)?;
+
                 let file_path = asset_dir.join("foo").with_file_name(screen_filename(
-                    screen.index as usize,
+                    index,
                     platform_name,
                     &platform.device,
                 ));
 
-                let alternate_file_path = platform.rom.rom_owner.as_ref().and_then(|parent| {
-                    Some(asset_dir.join("foo").with_file_name(screen_filename(
-                        screen.index as usize,
-                        &parent,
-                        &platform.device,
-                    )))
-                });
-
-                let bounds = screen.bounds.to_xy();
-
-                let x = if bounds.x >= 0 {
-                    // Only normalize to 0 if we started out positive
-                    (bounds.x - max_common_x).max(0)
-                } else {
-                    bounds.x - max_common_x
-                };
-
-                let y = if bounds.y >= 0 {
-                    // Only normalize to 0 if we started out positive
-                    (bounds.y - max_common_y).max(0)
+                // Explicitly named SVGs don't need to fall back to the parent's naming
+                let alternate_file_path = if platform.device.svgs.is_none() {
+                    platform.rom.rom_owner.as_ref().map(|parent| {
+                        asset_dir.join("foo").with_file_name(screen_filename(
+                            index,
+                            parent,
+                            &platform.device,
+                        ))
+                    })
                 } else {
-                    bounds.y - max_common_y
+                    None
                 };
 
-                let bounds = Bounds {
-                    x,
-                    y,
-                    width: bounds.width,
-                    height: bounds.height,
-                };
-
-                let dimensions =
-                    ImageDimensions::new(&view_bounds, &bounds, ratio, x_offset, y_offset);
+                let dimensions = ImageDimensions::new(
+                    &view_bounds,
+                    &screen.bounds.to_xy(),
+                    ratio,
+                    x_offset,
+                    y_offset,
+                );
 
-                // TODO: We don't really have a way to scale SVGs that won't result in a quality loss
-                // so that isn't handled here
-                let rendered_svg = build_svg(&file_path, &alternate_file_path, &dimensions)?;
+                let rendered_svg = svg_cache.get_or_build(
+                    &file_path,
+                    &alternate_file_path,
+                    &dimensions,
+                    &options.svg,
+                )?;
 
                 // Draw actual LCD pixels
                 mask_pixmap.draw_pixmap(
@@ -367,19 +389,40 @@ pub fn render(
                     None,
                 );
 
+                segment_ids.extend(rendered_svg.segment_ids.iter());
+                title_issues.extend(rendered_svg.title_issues.iter().cloned());
+
                 // Combine this screen into the global pixel ID map
-                // If both have IDs, latest wins
-                for i in 0..WIDTH * HEIGHT {
-                    if let Some(new_svg_id) = rendered_svg.pixel_pos_to_id[i] {
-                        // Use this, replacing any existing pixel
-                        pixels_to_mask_id[i] = Some(new_svg_id);
+                // If both have IDs, latest wins, and the replaced IDs are kept as shared
+                for (i, new_svg_id) in rendered_svg.pixel_pos_to_id.iter().enumerate() {
+                    guard!(let Some(new_svg_id) = new_svg_id else {
+                        continue;
+                    });
+
+                    if let Some(ids) = rendered_svg.shared_pixels.get(&i) {
+                        for id in ids {
+                            assign_segment_id(&mut pixels_to_mask_id, &mut shared_pixels, i, *id);
+                        }
                     }
+
+                    assign_segment_id(&mut pixels_to_mask_id, &mut shared_pixels, i, *new_svg_id);
                 }
             }
             ViewElement::Bounds(_) => {}
         }
     }
 
+    report_shared_pixels(&pixels_to_mask_id, &shared_pixels);
+
+    let segment_report = SegmentReport::new(
+        &segment_ids,
+        &pixels_to_mask_id,
+        &shared_pixels,
+        title_issues,
+    );
+
+    segment_report.print(options.segment_report);
+
     let mut output_mask = background_pixmap.clone();
 
     // Draw mask over top of background, so transparency can blend to the correct colors
@@ -392,7 +435,7 @@ pub fn render(
         None,
     );
 
-    if debug {
+    if options.debug {
         let debug_path = asset_dir.join(format!("{platform_name}.png"));
         let debug_background_path = asset_dir.join(format!("{platform_name}_background.png"));
         let debug_mask_path = asset_dir.join(format!("{platform_name}_mask.png"));
@@ -425,10 +468,59 @@ pub fn render(
     Ok(RenderedData {
         background_bytes: background_pixmap,
         mask_bytes: output_mask,
-        pixels_to_mask_id: pixels_to_mask_id,
+        pixels_to_mask_id,
+        shared_pixels,
+        segment_report,
     })
 }
 
+#[allow(clippy::diverging_sub_expression)]
+fn report_shared_pixels(
+    pixels_to_mask_id: &[Option<u16>],
+    shared_pixels: &BTreeMap<usize, Vec<u16>>,
+) {
+    if shared_pixels.is_empty() {
+        return;
+    }
+
+    // Count shared pixels for each pair of (primary, overlapped) segments
+    let mut pairs: HashMap<(u16, u16), usize> = HashMap::new();
+
+    for (i, ids) in shared_pixels {
+        guard!(let Some(primary_id) = pixels_to_mask_id[*i] else {
+            continue;
+        });
+
+        for id in ids {
+            *pairs.entry((primary_id, *id)).or_default() += 1;
+        }
+    }
+
+    let mut pairs = pairs.into_iter().collect::<Vec<((u16, u16), usize)>>();
+    pairs.sort_by(|(a_ids, a_count), (b_ids, b_count)| b_count.cmp(a_count).then(a_ids.cmp(b_ids)));
+
+    println!(
+        "{}",
+        format!(
+            "{} pixels are shared by multiple segments",
+            shared_pixels.len()
+        )
+        .yellow()
+    );
+
+    for ((primary_id, id), count) in pairs {
+        println!(
+            "  {} hides {} on {count} pixels",
+            format_segment_id(primary_id),
+            format_segment_id(id)
+        );
+    }
+}
+
+fn union_bounds(bounds: impl Iterator<Item = Bounds>) -> Option<Bounds> {
+    bounds.reduce(|a, b| a.union(&b))
+}
+
 fn alpha_blend_colors(
     background: PremultipliedColorU8,
     foreground: PremultipliedColorU8,
@@ -477,29 +569,58 @@ fn multiply_blend_colors(
         .expect("Could not convert multiply blend color")
 }
 
-fn screen_filename(index: usize, platform_name: &str, platform: &PresetDefinition) -> String {
-    let suffix = match platform.screen {
-        manifest::Screen::Single { .. } => "",
-        manifest::Screen::DualVertical { .. } => {
-            if index == 0 {
-                "_top"
-            } else {
-                "_bottom"
-            }
-        }
-        manifest::Screen::DualHorizontal { .. } => {
-            if index == 0 {
-                "_left"
-            } else {
-                "_right"
-            }
-        }
+pub fn screen_suffixes(screen: &manifest::Screen) -> &'static [&'static str] {
+    match screen {
+        manifest::Screen::Single { .. } => &[""],
+        manifest::Screen::DualVertical { .. } => &["_top", "_bottom"],
+        manifest::Screen::DualHorizontal { .. } => &["_left", "_right"],
+    }
+}
+
+///
+/// Resolve the LCD screen referenced by a layout `<screen>`, either by `index` or by `tag`
+///
+#[allow(clippy::diverging_sub_expression)]
+fn screen_index(screen: &Screen, device: &PresetDefinition) -> Result<usize, String> {
+    let suffixes = screen_suffixes(&device.screen);
+
+    let index = if let Some(index) = screen.index {
+        index
+    } else if let Some(tag) = &screen.tag {
+        // MAME tags screens with the same suffix as their SVG (`screen_top`), or just `screen` if there's only one
+        let tag = tag.trim_start_matches(':');
+        let suffix = tag.strip_prefix("screen").unwrap_or(tag);
+
+        guard!(let Some(index) = suffixes.iter().position(|s| *s == suffix) else {
+            return Err(format!("Could not match screen tag \"{tag}\" to a device screen"));
+        });
+
+        index as i32
+    } else {
+        return Err("Layout screen has neither an index nor a tag".to_string());
     };
 
+    if index < 0 || index as usize >= suffixes.len() {
+        return Err(format!(
+            "Layout screen index {index} is out of bounds for a device with {} screen(s)",
+            suffixes.len()
+        ));
+    }
+
+    Ok(index as usize)
+}
+
+pub fn screen_filename(index: usize, platform_name: &str, device: &PresetDefinition) -> String {
+    if let Some(name) = device.svgs.as_ref().and_then(|svgs| svgs.get(index)) {
+        return name.clone();
+    }
+
+    let suffix = screen_suffixes(&device.screen)[index];
+
     format!("{platform_name}{suffix}.svg")
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ImageDimensions {
     pub x: i32,
     pub y: i32,
@@ -515,8 +636,8 @@ impl ImageDimensions {
         x_offset: i32,
         y_offset: i32,
     ) -> Self {
-        let x = ((bounds.x as i32 - view_bounds.x as i32) as f32 * ratio).round() as i32;
-        let y = ((bounds.y as i32 - view_bounds.y as i32) as f32 * ratio).round() as i32;
+        let x = ((bounds.x - view_bounds.x) as f32 * ratio).round() as i32;
+        let y = ((bounds.y - view_bounds.y) as f32 * ratio).round() as i32;
         let width = (bounds.width as f32 * ratio) as u32;
         let height = (bounds.height as f32 * ratio) as u32;
 
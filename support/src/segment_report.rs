@@ -0,0 +1,211 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use colored::Colorize;
+
+use crate::{
+    svg_manage::{format_segment_id, TitleIssue},
+    WIDTH,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SegmentStats {
+    /// Pixels where this segment is the primary ID
+    pub pixel_count: usize,
+    /// Pixels where this segment is hidden by another overlapping segment
+    pub shared_count: usize,
+    /// Inclusive `(min_x, min_y, max_x, max_y)` of every pixel covered by the segment
+    pub bounds: (usize, usize, usize, usize),
+}
+
+///
+/// Per segment pixel coverage of a rendered game, with any problems found in the SVG titles
+///
+#[derive(Clone, Debug, Default)]
+pub struct SegmentReport {
+    pub segments: BTreeMap<u16, SegmentStats>,
+    /// IDs titled in an SVG that did not end up with any pixels
+    pub missing: BTreeSet<u16>,
+    /// IDs titled in an SVG whose pixels are all hidden by other segments
+    pub overwritten: BTreeSet<u16>,
+    pub title_issues: Vec<TitleIssue>,
+}
+
+impl SegmentReport {
+    pub fn new(
+        segment_ids: &BTreeSet<u16>,
+        pixels_to_mask_id: &[Option<u16>],
+        shared_pixels: &BTreeMap<usize, Vec<u16>>,
+        title_issues: Vec<TitleIssue>,
+    ) -> Self {
+        let mut segments: BTreeMap<u16, SegmentStats> = BTreeMap::new();
+
+        let mut add_pixel = |id: u16, i: usize, shared: bool| {
+            let (x, y) = (i % WIDTH, i / WIDTH);
+
+            let stats = segments.entry(id).or_insert(SegmentStats {
+                pixel_count: 0,
+                shared_count: 0,
+                bounds: (x, y, x, y),
+            });
+
+            if shared {
+                stats.shared_count += 1;
+            } else {
+                stats.pixel_count += 1;
+            }
+
+            let (min_x, min_y, max_x, max_y) = stats.bounds;
+            stats.bounds = (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y));
+        };
+
+        for (i, id) in pixels_to_mask_id.iter().enumerate() {
+            if let Some(id) = id {
+                add_pixel(*id, i, false);
+            }
+        }
+
+        for (i, ids) in shared_pixels {
+            for id in ids {
+                add_pixel(*id, *i, true);
+            }
+        }
+
+        let mut missing = BTreeSet::new();
+        let mut overwritten = BTreeSet::new();
+
+        for id in segment_ids {
+            match segments.get(id) {
+                None => {
+                    missing.insert(*id);
+                }
+                Some(stats) if stats.pixel_count == 0 => {
+                    overwritten.insert(*id);
+                }
+                _ => {}
+            }
+        }
+
+        SegmentReport {
+            segments,
+            missing,
+            overwritten,
+            title_issues,
+        }
+    }
+
+    pub fn has_problems(&self) -> bool {
+        !self.missing.is_empty() || !self.overwritten.is_empty() || !self.title_issues.is_empty()
+    }
+
+    ///
+    /// Print the problems found. If `verbose`, also print the coverage of every segment
+    ///
+    pub fn print(&self, verbose: bool) {
+        if verbose {
+            println!("Segment  Pixels  Hidden  Bounds");
+
+            for (id, stats) in &self.segments {
+                let (min_x, min_y, max_x, max_y) = stats.bounds;
+
+                println!(
+                    "{:<8} {:>6}  {:>6}  ({min_x}, {min_y}) - ({max_x}, {max_y})",
+                    format_segment_id(*id),
+                    stats.pixel_count,
+                    stats.shared_count
+                );
+            }
+
+            println!(
+                "{} segments with pixels, {} without",
+                self.segments.len() - self.overwritten.len(),
+                self.missing.len() + self.overwritten.len()
+            );
+        }
+
+        for id in &self.missing {
+            println!(
+                "{}",
+                format!(
+                    "Segment {} has no pixels. It may be outside of the screen or image",
+                    format_segment_id(*id)
+                )
+                .yellow()
+            );
+        }
+
+        for id in &self.overwritten {
+            println!(
+                "{}",
+                format!(
+                    "Segment {} is completely hidden by other segments",
+                    format_segment_id(*id)
+                )
+                .yellow()
+            );
+        }
+
+        for issue in &self.title_issues {
+            let element = issue
+                .element_id
+                .as_ref()
+                .map_or("untitled element".to_string(), |id| format!("\"{id}\""));
+
+            println!(
+                "{}",
+                if issue.rejected {
+                    format!("Rejected segment title on {element}: {}", issue.message)
+                } else {
+                    format!("Accepted segment title on {element}: {}", issue.message)
+                }
+                .yellow()
+            );
+        }
+    }
+
+    ///
+    /// Print the segments whose pixel counts changed since a previous build of the same game
+    ///
+    pub fn print_diff(&self, previous: &SegmentReport) {
+        let ids = self
+            .segments
+            .keys()
+            .chain(previous.segments.keys())
+            .collect::<BTreeSet<&u16>>();
+
+        let mut changed_count = 0;
+
+        for id in ids {
+            let count = |report: &SegmentReport| {
+                report.segments.get(id).map_or(0, |stats| stats.pixel_count)
+            };
+
+            let (before, after) = (count(previous), count(self));
+
+            if before == after {
+                continue;
+            }
+
+            changed_count += 1;
+
+            let line = format!(
+                "{:<8} {before:>6} -> {after:>6} ({:+})",
+                format_segment_id(*id),
+                after as i64 - before as i64
+            );
+
+            if after == 0 {
+                println!("{}", line.red());
+            } else if before == 0 {
+                println!("{}", line.green());
+            } else {
+                println!("{line}");
+            }
+        }
+
+        if changed_count == 0 {
+            println!("No segment pixel counts changed");
+        } else {
+            println!("{changed_count} segment(s) changed");
+        }
+    }
+}
@@ -0,0 +1,681 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use colored::Colorize;
+use regex::Regex;
+
+use crate::svg_manage::{format_segment_id, parse_title, ParsedTitle};
+
+/// The documented title ranges, which are narrower than what `parse_title` accepts
+const MAX_SEGMENT: u16 = 2;
+
+/// Shapes that usvg converts to paths, but which can never be matched to a title
+const SHAPES: [&str; 6] = ["rect", "circle", "ellipse", "line", "polyline", "polygon"];
+
+/// Elements whose contents are never drawn directly
+const NON_RENDERED: [&str; 6] = ["defs", "clipPath", "mask", "pattern", "symbol", "marker"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    /// The SVG won't build, or segments will be missing
+    Error,
+    /// The SVG builds, but may not be what was intended
+    Warning,
+}
+
+pub struct LintIssue {
+    pub line: usize,
+    pub severity: Severity,
+    /// A short identifier of the check that failed
+    pub rule: &'static str,
+    pub message: String,
+}
+
+enum Tag<'a> {
+    Start {
+        name: &'a str,
+        attributes: &'a str,
+        self_closing: bool,
+    },
+    End {
+        name: &'a str,
+    },
+    Text(&'a str),
+}
+
+///
+/// An XML tag or text, with its byte offset in the file
+///
+struct Token<'a> {
+    offset: usize,
+    tag: Tag<'a>,
+}
+
+///
+/// A minimal XML tokenizer that keeps byte offsets, so issues can be reported by line. Comments, processing
+/// instructions, and doctypes are skipped
+///
+#[allow(clippy::diverging_sub_expression)]
+fn tokenize(contents: &str) -> Result<Vec<Token<'_>>, String> {
+    let mut tokens = vec![];
+    let mut position = 0;
+
+    let find_from = |pattern: &str, from: usize| -> Result<usize, String> {
+        contents[from..]
+            .find(pattern)
+            .map(|index| from + index)
+            .ok_or_else(|| format!("Unterminated markup, expected \"{pattern}\""))
+    };
+
+    while let Some(index) = contents[position..].find('<') {
+        let start = position + index;
+
+        if start > position {
+            tokens.push(Token {
+                offset: position,
+                tag: Tag::Text(&contents[position..start]),
+            });
+        }
+
+        let rest = &contents[start..];
+
+        position = if rest.starts_with("<!--") {
+            find_from("-->", start)? + 3
+        } else if rest.starts_with("<?") {
+            find_from("?>", start)? + 2
+        } else if rest.starts_with("<![CDATA[") {
+            let end = find_from("]]>", start)?;
+
+            tokens.push(Token {
+                offset: start,
+                tag: Tag::Text(&contents[start + 9..end]),
+            });
+
+            end + 3
+        } else if rest.starts_with("<!") {
+            find_from(">", start)? + 1
+        } else if let Some(name) = rest.strip_prefix("</") {
+            let end = find_from(">", start)?;
+
+            tokens.push(Token {
+                offset: start,
+                tag: Tag::End {
+                    name: name[..end - start - 2].trim(),
+                },
+            });
+
+            end + 1
+        } else {
+            // Attribute values may contain `>`, so skip over quoted strings
+            let mut quote = None;
+            let mut end = None;
+
+            for (i, c) in rest.char_indices().skip(1) {
+                match (quote, c) {
+                    (Some(q), c) if c == q => quote = None,
+                    (Some(_), _) => {}
+                    (None, '"' | '\'') => quote = Some(c),
+                    (None, '>') => {
+                        end = Some(start + i);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+
+            guard!(let Some(end) = end else {
+                return Err("Unterminated tag".to_string());
+            });
+
+            let inner = &contents[start + 1..end];
+            let self_closing = inner.ends_with('/');
+            let inner = inner.trim_end_matches('/');
+
+            let name_end = inner
+                .find(|c: char| c.is_whitespace())
+                .unwrap_or(inner.len());
+
+            tokens.push(Token {
+                offset: start,
+                tag: Tag::Start {
+                    name: &inner[..name_end],
+                    attributes: &inner[name_end..],
+                    self_closing,
+                },
+            });
+
+            end + 1
+        };
+    }
+
+    Ok(tokens)
+}
+
+/// Strip any namespace prefix, such as `svg:path`
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+#[derive(Debug)]
+struct SvgElement {
+    name: String,
+    line: usize,
+    offset: usize,
+    id: Option<String>,
+    /// The raw title text, and the line of the title
+    title: Option<(String, usize)>,
+    parent: Option<usize>,
+}
+
+pub struct LintResult {
+    pub issues: Vec<LintIssue>,
+    /// Byte offsets of the `<path` tags that have no id
+    missing_id_offsets: Vec<usize>,
+    ids: HashSet<String>,
+}
+
+///
+/// Check a segment SVG for title problems that would cause segments to be missing or mislabeled
+///
+#[allow(clippy::diverging_sub_expression)]
+pub fn lint_svg(contents: &str) -> Result<LintResult, String> {
+    let line_starts = std::iter::once(0)
+        .chain(contents.match_indices('\n').map(|(i, _)| i + 1))
+        .collect::<Vec<usize>>();
+    let line_of = |offset: usize| line_starts.partition_point(|start| *start <= offset);
+
+    let id_regex = Regex::new(r#"(?:^|\s)id\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+
+    let mut issues = vec![];
+    let mut elements: Vec<SvgElement> = vec![];
+    // Indexes into `elements` of the currently open tags
+    let mut stack: Vec<usize> = vec![];
+    let mut title_text: Option<(String, usize)> = None;
+
+    let mut issue = |line: usize, severity: Severity, rule: &'static str, message: String| {
+        issues.push(LintIssue {
+            line,
+            severity,
+            rule,
+            message,
+        })
+    };
+
+    for token in tokenize(contents)? {
+        let line = line_of(token.offset);
+
+        match token.tag {
+            Tag::Start {
+                name,
+                attributes,
+                self_closing,
+            } => {
+                let name = local_name(name);
+
+                if name == "title" {
+                    if title_text.is_some() {
+                        issue(
+                            line,
+                            Severity::Error,
+                            "nested-title",
+                            "Titles can't be nested. The generator will fail to load this SVG"
+                                .to_string(),
+                        );
+                    }
+
+                    if !self_closing {
+                        title_text = Some((String::new(), line));
+                    }
+                    continue;
+                }
+
+                if name == "path" {
+                    if let Some(ancestor) = stack.iter().find(|i| elements[**i].name == "path") {
+                        issue(
+                            line,
+                            Severity::Error,
+                            "nested-path",
+                            format!(
+                                "Path is nested inside the path at line {}. The generator will fail to load this SVG",
+                                elements[*ancestor].line
+                            ),
+                        );
+                    }
+                }
+
+                let id = id_regex.captures(attributes).and_then(|captures| {
+                    captures
+                        .get(1)
+                        .or(captures.get(2))
+                        .map(|id| id.as_str().to_string())
+                });
+
+                elements.push(SvgElement {
+                    name: name.to_string(),
+                    line,
+                    offset: token.offset,
+                    id,
+                    title: None,
+                    parent: stack.last().copied(),
+                });
+
+                if !self_closing {
+                    stack.push(elements.len() - 1);
+                }
+            }
+            Tag::Text(text) => {
+                if let Some((title, _)) = &mut title_text {
+                    title.push_str(text);
+                }
+            }
+            Tag::End { name } => {
+                let name = local_name(name);
+
+                if name == "title" {
+                    guard!(let Some((title, title_line)) = title_text.take() else {
+                        issue(line, Severity::Error, "unmatched-tag", "Closing title has no opening tag".to_string());
+                        continue;
+                    });
+
+                    // Only path and group titles name segments
+                    if let Some(parent) = stack.last() {
+                        let parent = &mut elements[*parent];
+
+                        if parent.name == "path"
+                            || parent.name == "g"
+                            || SHAPES.contains(&parent.name.as_str())
+                        {
+                            parent.title = Some((title.trim().to_string(), title_line));
+                        }
+                    }
+                    continue;
+                }
+
+                match stack.pop() {
+                    Some(index) if elements[index].name == name => {}
+                    _ => issue(
+                        line,
+                        Severity::Error,
+                        "unmatched-tag",
+                        format!("Closing {name} doesn't match the open element"),
+                    ),
+                }
+            }
+        }
+    }
+
+    let ancestors = |index: usize| {
+        std::iter::successors(elements[index].parent, |parent| elements[*parent].parent)
+    };
+
+    // Titles are parsed once, so each problem is only reported at the title itself
+    let mut parsed_titles: HashMap<usize, u16> = HashMap::new();
+    let mut first_use: HashMap<u16, usize> = HashMap::new();
+
+    for (index, element) in elements.iter().enumerate() {
+        guard!(let Some((title, title_line)) = &element.title else {
+            continue;
+        });
+
+        let rule_for = |message: &str| {
+            if message.contains("out of bounds") {
+                "out-of-range"
+            } else {
+                "malformed-title"
+            }
+        };
+
+        match parse_title(title) {
+            Ok(ParsedTitle { id, warning }) => {
+                if let Some(message) = warning {
+                    issue(*title_line, Severity::Warning, rule_for(&message), message);
+                }
+
+                if id >> 6 > MAX_SEGMENT {
+                    issue(
+                        *title_line,
+                        Severity::Warning,
+                        "out-of-range",
+                        format!(
+                            "Title {title} has segment {}. Segments are 0 to {MAX_SEGMENT}",
+                            id >> 6
+                        ),
+                    );
+                }
+
+                if let Some(first_line) = first_use.get(&id) {
+                    issue(
+                        *title_line,
+                        Severity::Warning,
+                        "duplicate-title",
+                        format!(
+                            "Segment {} is also titled at line {first_line}",
+                            format_segment_id(id)
+                        ),
+                    );
+                } else {
+                    first_use.insert(id, *title_line);
+                }
+
+                parsed_titles.insert(index, id);
+            }
+            Err(message) => issue(*title_line, Severity::Error, rule_for(&message), message),
+        }
+    }
+
+    let mut missing_id_offsets = vec![];
+
+    for (index, element) in elements.iter().enumerate() {
+        let is_path = element.name == "path";
+
+        if !is_path && !SHAPES.contains(&element.name.as_str()) {
+            continue;
+        }
+
+        if ancestors(index).any(|a| NON_RENDERED.contains(&elements[a].name.as_str())) {
+            continue;
+        }
+
+        // Own titles win, then the nearest titled group, as in `correlate_id_to_title`
+        let own_title = parsed_titles.get(&index).copied();
+        let group_title = ancestors(index)
+            .filter(|a| elements[*a].name == "g")
+            .find_map(|a| {
+                parsed_titles
+                    .get(&a)
+                    .map(|title| (*title, elements[a].line))
+            });
+
+        let kind = &element.name;
+
+        match (is_path, &element.id, own_title, group_title) {
+            (false, _, Some(_), _) => issue(
+                element.line,
+                Severity::Error,
+                "untitled-child",
+                format!("Titled <{kind}> is never lit, as only paths can be titled. Convert it to a path"),
+            ),
+            (false, _, None, Some((title, group_line))) => issue(
+                element.line,
+                Severity::Error,
+                "untitled-child",
+                format!(
+                    "<{kind}> in the group titled {} at line {group_line} is never lit, as only paths inherit titles. Convert it to a path",
+                    format_segment_id(title)
+                ),
+            ),
+            (true, None, Some(title), _) | (true, None, None, Some((title, _))) => {
+                missing_id_offsets.push(element.offset);
+
+                issue(
+                    element.line,
+                    Severity::Error,
+                    "missing-id",
+                    format!(
+                        "Path has no id, so it's dropped from segment {}",
+                        format_segment_id(title)
+                    ),
+                );
+            }
+            (true, None, None, None) => {
+                missing_id_offsets.push(element.offset);
+
+                issue(
+                    element.line,
+                    Severity::Warning,
+                    "missing-id",
+                    "Path has no id or title, and is ignored".to_string(),
+                );
+            }
+            (_, Some(id), None, None) => {
+                let reason = if element.title.is_some() {
+                    "has an invalid title"
+                } else {
+                    "has no title and isn't in a titled group"
+                };
+
+                issue(
+                    element.line,
+                    Severity::Warning,
+                    "hidden-path",
+                    format!("<{kind}> \"{id}\" {reason}, so it's removed before rendering"),
+                )
+            }
+            _ => {}
+        }
+    }
+
+    issues.sort_by_key(|issue| issue.line);
+
+    Ok(LintResult {
+        issues,
+        missing_id_offsets,
+        ids: elements.into_iter().filter_map(|e| e.id).collect(),
+    })
+}
+
+///
+/// Give every path without an id a unique one. Returns the updated SVG and the number of ids added
+///
+pub fn fix_missing_ids(contents: &str, result: &LintResult) -> (String, usize) {
+    let mut output = contents.to_string();
+    let mut next_id = 0;
+    let mut new_ids = vec![];
+
+    for _ in &result.missing_id_offsets {
+        let id = loop {
+            next_id += 1;
+            let id = format!("path_lint{next_id}");
+
+            if !result.ids.contains(&id) {
+                break id;
+            }
+        };
+
+        new_ids.push(id);
+    }
+
+    // Insert from the end, so earlier offsets stay valid
+    for (offset, id) in result.missing_id_offsets.iter().zip(new_ids).rev() {
+        let name_end = offset
+            + output[*offset..]
+                .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+                .unwrap_or(0);
+
+        output.insert_str(name_end, &format!(" id=\"{id}\""));
+    }
+
+    (output, result.missing_id_offsets.len())
+}
+
+///
+/// Lint each SVG, printing the issues. With `fix`, missing path ids are assigned and the file is rewritten. Returns
+/// false if any file has errors
+///
+pub fn lint_files(paths: &[PathBuf], fix: bool) -> bool {
+    let mut error_count = 0;
+    let mut warning_count = 0;
+
+    for path in paths {
+        let result = lint_file(path, fix);
+
+        match result {
+            Ok(issues) => {
+                for issue in &issues {
+                    let (label, count) = match issue.severity {
+                        Severity::Error => ("error".red(), &mut error_count),
+                        Severity::Warning => ("warning".yellow(), &mut warning_count),
+                    };
+
+                    *count += 1;
+
+                    println!(
+                        "{}:{}: {label}: {} [{}]",
+                        path.display(),
+                        issue.line,
+                        issue.message,
+                        issue.rule
+                    );
+                }
+            }
+            Err(err) => {
+                println!("{}: {}: {err}", path.display(), "error".red());
+                error_count += 1;
+            }
+        }
+    }
+
+    println!("-------------------------");
+    println!("Errors: {error_count}, Warnings: {warning_count}");
+
+    error_count == 0
+}
+
+fn lint_file(path: &Path, fix: bool) -> Result<Vec<LintIssue>, String> {
+    let contents = fs::read_to_string(path).map_err(|err| format!("Could not read SVG: {err}"))?;
+
+    let result = lint_svg(&contents)?;
+
+    if !fix || result.missing_id_offsets.is_empty() {
+        return Ok(result.issues);
+    }
+
+    let (fixed, count) = fix_missing_ids(&contents, &result);
+
+    fs::write(path, &fixed).map_err(|err| format!("Could not write SVG: {err}"))?;
+
+    println!("{}: Assigned {count} missing path id(s)", path.display());
+
+    // Report what's left after fixing
+    Ok(lint_svg(&fixed)?.issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn describe(contents: &str) -> Vec<String> {
+        tokenize(contents)
+            .unwrap()
+            .into_iter()
+            .map(|token| match token.tag {
+                Tag::Start {
+                    name,
+                    attributes,
+                    self_closing,
+                } => format!(
+                    "{}<{name}{attributes}{}>",
+                    token.offset,
+                    if self_closing { "/" } else { "" }
+                ),
+                Tag::End { name } => format!("{}</{name}>", token.offset),
+                Tag::Text(text) => format!("{}{text}", token.offset),
+            })
+            .collect()
+    }
+
+    fn rules(contents: &str) -> Vec<(usize, Severity, &'static str)> {
+        lint_svg(contents)
+            .unwrap()
+            .issues
+            .into_iter()
+            .map(|issue| (issue.line, issue.severity, issue.rule))
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_tags_and_text() {
+        assert_eq!(
+            describe("<svg a=\"1\"><g>text</g><path/></svg>"),
+            vec![
+                "0<svg a=\"1\">",
+                "11<g>",
+                "14text",
+                "18</g>",
+                "22<path/>",
+                "29</svg>"
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_skips_markup() {
+        assert_eq!(
+            describe("<?xml version=\"1.0\"?><!DOCTYPE svg><!-- <path> --><svg/>"),
+            vec!["50<svg/>"]
+        );
+    }
+
+    #[test]
+    fn tokenize_quoted_attributes_and_cdata() {
+        assert_eq!(
+            describe("<path d='a>b' id=\"c\"/><![CDATA[<x>]]>"),
+            vec!["0<path d='a>b' id=\"c\"/>", "22<x>"]
+        );
+    }
+
+    #[test]
+    fn tokenize_rejects_unterminated_markup() {
+        assert!(tokenize("<svg").is_err());
+        assert!(tokenize("<path d=\"a>").is_err());
+        assert!(tokenize("<!-- open").is_err());
+    }
+
+    #[test]
+    fn lint_accepts_titled_paths() {
+        let svg = "<svg>\n<g><title>1.2.3</title>\n<path id=\"a\"/>\n</g>\n<path id=\"b\"><title>0.0.0</title></path>\n</svg>";
+
+        assert!(rules(svg).is_empty());
+    }
+
+    #[test]
+    fn lint_reports_title_problems() {
+        let svg = "<svg>\n<path id=\"a\"><title>1.2.3</title></path>\n<path id=\"b\"><title>1.2.3</title></path>\n<path id=\"c\"><title>1.2.4</title></path>\n<path id=\"d\"><title>x</title></path>\n</svg>";
+
+        assert_eq!(
+            rules(svg),
+            vec![
+                (3, Severity::Warning, "duplicate-title"),
+                (4, Severity::Warning, "out-of-range"),
+                (5, Severity::Error, "malformed-title"),
+                (5, Severity::Warning, "hidden-path"),
+            ]
+        );
+    }
+
+    #[test]
+    fn lint_reports_structure_problems() {
+        let svg = "<svg>\n<path id=\"a\">\n<path id=\"b\"/>\n</path>\n<rect><title>0.0.0</title></rect>\n</svg>";
+
+        assert_eq!(
+            rules(svg),
+            vec![
+                (2, Severity::Warning, "hidden-path"),
+                (3, Severity::Error, "nested-path"),
+                (3, Severity::Warning, "hidden-path"),
+                (5, Severity::Error, "untitled-child"),
+            ]
+        );
+    }
+
+    #[test]
+    fn fix_adds_missing_ids() {
+        let svg =
+            "<svg><g><title>0.0.0</title><path d=\"\"/><path/></g><path id=\"path_lint1\"/></svg>";
+        let result = lint_svg(svg).unwrap();
+
+        let (fixed, count) = fix_missing_ids(svg, &result);
+
+        assert_eq!(count, 2);
+        assert_eq!(
+            fixed,
+            "<svg><g><title>0.0.0</title><path id=\"path_lint2\" d=\"\"/><path id=\"path_lint3\"/></g><path id=\"path_lint1\"/></svg>"
+        );
+        assert!(!rules(&fixed)
+            .iter()
+            .any(|(_, _, rule)| *rule == "missing-id"));
+    }
+}
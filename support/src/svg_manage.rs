@@ -1,16 +1,15 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fs,
     path::PathBuf,
 };
 
 use resvg::{
-    tiny_skia::{self, Pixmap, PixmapPaint, PremultipliedColorU8},
+    tiny_skia::{Pixmap, PremultipliedColorU8},
     usvg::{self, NodeKind, Tree, TreeParsing},
     FitTo,
 };
 
-use rctree;
 use svg::{self, node::element::tag::Type};
 use tiny_skia_path::Transform;
 
@@ -19,12 +18,26 @@ use crate::{render::ImageDimensions, HEIGHT, WIDTH};
 pub struct RenderedSVG {
     pub pixmap: Pixmap,
     pub pixel_pos_to_id: Vec<Option<u16>>,
+    /// Segment IDs overwritten by a later segment at the same pixel, in draw order
+    pub shared_pixels: BTreeMap<usize, Vec<u16>>,
+    /// Every segment ID titled in the SVG, whether or not it has any pixels
+    pub segment_ids: BTreeSet<u16>,
+    pub title_issues: Vec<TitleIssue>,
 }
 
+pub struct SvgRasterOptions {
+    /// Segments are rasterized at this multiple of the output resolution, then downsampled
+    pub supersample: u32,
+    /// The fraction of a pixel a segment must cover (exclusive) for the pixel to be assigned to it
+    pub coverage_threshold: f32,
+}
+
+#[allow(clippy::diverging_sub_expression)]
 pub fn build_svg(
     svg_path: &PathBuf,
     alternate_svg_path: &Option<PathBuf>,
     dimensions: &ImageDimensions,
+    options: &SvgRasterOptions,
 ) -> Result<RenderedSVG, String> {
     // Actual SVG ID (so `path123`) to title field (the segment ID)
     let svg_error = |path: &PathBuf| format!("Could not load SVG at {path:?}");
@@ -41,7 +54,7 @@ pub fn build_svg(
         return Err(svg_error(svg_path));
     };
 
-    let svg_id_to_title = correlate_id_to_title(&contents)?;
+    let (svg_id_to_title, title_issues) = correlate_id_to_title(&contents)?;
 
     let tree = usvg::Tree::from_str(&contents, &usvg::Options::default()).unwrap();
 
@@ -60,33 +73,43 @@ pub fn build_svg(
     for node in tree.root.descendants() {
         let element = node.borrow();
 
-        match *element {
-            usvg::NodeKind::Path(ref path) => {
-                // Check if we care about this path
-                guard!(let Some(title) = svg_id_to_title.get(&path.id) else {
-                    continue;
-                });
-
-                let mut owning_tree = Node::new(element.clone());
-                let mut next_parent = node.parent();
+        if let usvg::NodeKind::Path(ref path) = *element {
+            // Check if we care about this path
+            guard!(let Some(title) = svg_id_to_title.get(&path.id) else {
+                continue;
+            });
 
-                while let Some(parent) = next_parent {
-                    let new_parent = Node::new(parent.borrow().clone());
-                    new_parent.append(owning_tree);
-                    owning_tree = new_parent;
+            let mut owning_tree = Node::new(element.clone());
+            let mut next_parent = node.parent();
 
-                    next_parent = parent.parent();
-                }
+            while let Some(parent) = next_parent {
+                let new_parent = Node::new(parent.borrow().clone());
+                new_parent.append(owning_tree);
+                owning_tree = new_parent;
 
-                title_trees.push((owning_tree, *title));
+                next_parent = parent.parent();
             }
-            _ => {}
+
+            title_trees.push((owning_tree, *title));
         }
     }
 
-    let mut id_mask_pixmap = Pixmap::new(WIDTH as u32, HEIGHT as u32).unwrap();
+    let scale = options.supersample.max(1) as usize;
+    let transform = screen_transform(&tree, dimensions).post_scale(scale as f32, scale as f32);
+
+    let scaled_width = WIDTH * scale;
+
+    let mut id_mask_pixmap = Pixmap::new((WIDTH * scale) as u32, (HEIGHT * scale) as u32).unwrap();
+
+    // MAME clips screen contents to the screen bounds, so only pixels inside of them can belong to a segment
+    let (min_x, min_y, max_x, max_y) = screen_region(dimensions);
+
+    // Summed alpha of the subpixels covered by the current segment, per output pixel
+    let mut coverage: Vec<u32> = vec![0; WIDTH * HEIGHT];
+    let full_coverage = (255 * scale * scale) as f32;
 
     let mut pixel_pos_to_id: Vec<Option<u16>> = vec![None; WIDTH * HEIGHT];
+    let mut shared_pixels: BTreeMap<usize, Vec<u16>> = BTreeMap::new();
 
     // Extract pixel to ID mapping
     for (title_tree, id) in title_trees {
@@ -96,118 +119,226 @@ pub fn build_svg(
             root: title_tree,
         };
 
-        let mut render_pixmap = Pixmap::new(dimensions.width, dimensions.height).unwrap();
+        resvg::render(&tree, FitTo::Original, transform, id_mask_pixmap.as_mut())
+            .expect("Could not render SVG to bitmap");
 
-        resvg::render(
-            &tree,
-            FitTo::Size(dimensions.width, dimensions.height),
-            tiny_skia::Transform::default(),
-            render_pixmap.as_mut(),
-        )
-        .expect("Could not render SVG to bitmap");
+        let pixels = id_mask_pixmap.pixels_mut();
 
-        // This is very wasteful and exists just to transform the coordinates, but I'm lazy
-        id_mask_pixmap.draw_pixmap(
-            dimensions.x,
-            dimensions.y,
-            render_pixmap.as_ref(),
-            &PixmapPaint::default(),
-            Transform::identity(),
-            None,
-        );
+        for y in min_y * scale..max_y * scale {
+            for x in min_x * scale..max_x * scale {
+                let i = y * scaled_width + x;
 
-        let pixels = id_mask_pixmap.pixels_mut();
+                let alpha = pixels[i].alpha();
+                if alpha == 0 {
+                    // Skip this pixel
+                    continue;
+                }
 
-        for i in 0..WIDTH * HEIGHT {
-            let pixel = pixels[i];
-            if pixel.alpha() == 0 {
-                // Skip this pixel
-                continue;
+                coverage[(y / scale) * WIDTH + x / scale] += alpha as u32;
+
+                // Zero out this pixel for next render
+                pixels[i] = PremultipliedColorU8::TRANSPARENT;
             }
+        }
 
-            // Copy id to a pixel indexed array
-            pixel_pos_to_id[i] = Some(id);
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let i = y * WIDTH + x;
 
-            // Zero out this pixel for next render
-            pixels[i] = PremultipliedColorU8::from_rgba(0, 0, 0, 0).unwrap();
+                if coverage[i] > 0
+                    && coverage[i] as f32 / full_coverage > options.coverage_threshold
+                {
+                    // Copy id to a pixel indexed array
+                    assign_segment_id(&mut pixel_pos_to_id, &mut shared_pixels, i, id);
+                }
+
+                coverage[i] = 0;
+            }
         }
     }
 
-    // This scales proportionally, which is not always what MAME does (gnw_cgrab)
-    let mut render_pixmap = Pixmap::new(dimensions.width, dimensions.height).unwrap();
-    resvg::render(
-        &tree,
-        FitTo::Size(dimensions.width, dimensions.height),
-        tiny_skia::Transform::default(),
-        render_pixmap.as_mut(),
-    )
-    .expect("Could not render SVG to bitmap");
-
-    // This is inefficient, but it transforms the coordinates for us
-    let mut mask_pixmap = Pixmap::new(WIDTH as u32, HEIGHT as u32).unwrap();
-    mask_pixmap.draw_pixmap(
-        dimensions.x,
-        dimensions.y,
-        render_pixmap.as_ref(),
-        &PixmapPaint::default(),
-        Transform::identity(),
-        None,
-    );
+    let mut mask_pixmap = Pixmap::new((WIDTH * scale) as u32, (HEIGHT * scale) as u32).unwrap();
+    resvg::render(&tree, FitTo::Original, transform, mask_pixmap.as_mut())
+        .expect("Could not render SVG to bitmap");
+
+    let mask_pixmap = downsample(mask_pixmap, scale);
 
     Ok(RenderedSVG {
         pixmap: mask_pixmap,
         pixel_pos_to_id,
+        shared_pixels,
+        segment_ids: svg_id_to_title.values().copied().collect(),
+        title_issues,
     })
 }
 
-fn parse_title(title: &str) -> Option<u16> {
-    let mut sections = title.split(".");
-
-    guard!(let Ok(segment) = sections.next()?.parse::<u8>() else {
-        println!("Could not parse segment from title {title}");
-        return None;
-    });
+///
+/// Assign a segment ID to a pixel. If both have IDs, latest wins, but the previous ID is retained as shared
+///
+pub fn assign_segment_id(
+    pixel_pos_to_id: &mut [Option<u16>],
+    shared_pixels: &mut BTreeMap<usize, Vec<u16>>,
+    index: usize,
+    id: u16,
+) {
+    if let Some(existing_id) = pixel_pos_to_id[index] {
+        if existing_id == id {
+            return;
+        }
 
-    if segment > 15 {
-        println!("Segment {segment} in {title} was out of bounds");
-        return None;
+        let shared = shared_pixels.entry(index).or_default();
+        shared.retain(|shared_id| *shared_id != id);
+        shared.push(existing_id);
     }
 
-    let segment = segment as u16;
+    pixel_pos_to_id[index] = Some(id);
+}
 
-    guard!(let Ok(column) = sections.next()?.parse::<u8>() else {
-        println!("Could not parse column from title {title}");
-        return None;
-    });
+/// Formats a segment ID back into its `x.y.z` title form
+pub fn format_segment_id(id: u16) -> String {
+    format!("{}.{}.{}", id >> 6, (id >> 2) & 0xF, id & 0x3)
+}
 
-    if column > 15 {
-        println!("Column {column} in {title} was out of bounds");
-        return None;
-    }
+/// Stretches the SVG to exactly fill the screen bounds, positioned within the full output image.
+/// MAME scales each axis independently (see gnw_cgrab), so the SVG's aspect ratio is not preserved
+fn screen_transform(tree: &Tree, dimensions: &ImageDimensions) -> Transform {
+    let x_scale = dimensions.width as f32 / tree.size.width() as f32;
+    let y_scale = dimensions.height as f32 / tree.size.height() as f32;
+
+    Transform::from_row(
+        x_scale,
+        0.0,
+        0.0,
+        y_scale,
+        dimensions.x as f32,
+        dimensions.y as f32,
+    )
+}
 
-    let column = column as u16;
+/// The output pixel region covered by the screen, clipped to the image
+fn screen_region(dimensions: &ImageDimensions) -> (usize, usize, usize, usize) {
+    let clamp = |value: i32, max: usize| value.clamp(0, max as i32) as usize;
 
-    guard!(let Ok(row_h) = sections.next()?.parse::<u8>() else {
-        println!("Could not parse row_h from title {title}");
-        return None;
-    });
+    (
+        clamp(dimensions.x, WIDTH),
+        clamp(dimensions.y, HEIGHT),
+        clamp(dimensions.x + dimensions.width as i32, WIDTH),
+        clamp(dimensions.y + dimensions.height as i32, HEIGHT),
+    )
+}
 
-    if row_h > 4 {
-        println!("Row {row_h} in {title} was out of bounds");
-        return None;
+/// Box filters a supersampled pixmap back down to the output resolution
+fn downsample(pixmap: Pixmap, scale: usize) -> Pixmap {
+    if scale == 1 {
+        return pixmap;
     }
 
-    let row_h = row_h as u16;
+    let mut output = Pixmap::new(WIDTH as u32, HEIGHT as u32).unwrap();
+
+    let source_pixels = pixmap.pixels();
+    let output_pixels = output.pixels_mut();
+    let samples = (scale * scale) as u32;
+
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let mut sum = [0u32; 4];
+
+            for sub_y in 0..scale {
+                let row = (y * scale + sub_y) * WIDTH * scale;
+
+                for sub_x in 0..scale {
+                    let pixel = source_pixels[row + x * scale + sub_x];
+
+                    sum[0] += pixel.red() as u32;
+                    sum[1] += pixel.green() as u32;
+                    sum[2] += pixel.blue() as u32;
+                    sum[3] += pixel.alpha() as u32;
+                }
+            }
 
-    if sections.next() != None {
-        println!("Title contained too many groups");
+            // Averaging premultiplied values keeps each color channel at or below alpha
+            output_pixels[y * WIDTH + x] = PremultipliedColorU8::from_rgba(
+                (sum[0] / samples) as u8,
+                (sum[1] / samples) as u8,
+                (sum[2] / samples) as u8,
+                (sum[3] / samples) as u8,
+            )
+            .unwrap();
+        }
     }
 
-    return Some((segment << 6) | (column << 2) | row_h);
+    output
+}
+
+///
+/// A parsed segment title. Problems that earlier versions of the generator tolerated are kept as a warning, rather than
+/// rejecting the title, so existing artwork keeps building
+///
+pub struct ParsedTitle {
+    pub id: u16,
+    pub warning: Option<String>,
 }
 
-fn correlate_id_to_title(contents: &String) -> Result<HashMap<String, u16>, String> {
+#[allow(clippy::diverging_sub_expression)]
+pub fn parse_title(title: &str) -> Result<ParsedTitle, String> {
+    let mut sections = title.split('.');
+
+    let mut parse_section = |name: &str, max: u8| -> Result<u16, String> {
+        guard!(let Some(section) = sections.next() else {
+            return Err(format!("Title {title} is missing the {name}"));
+        });
+
+        guard!(let Ok(value) = section.trim().parse::<u8>() else {
+            return Err(format!("Could not parse {name} from title {title}"));
+        });
+
+        if value > max {
+            return Err(format!("{name} {value} in {title} was out of bounds"));
+        }
+
+        Ok(value as u16)
+    };
+
+    let segment = parse_section("Segment", 15)?;
+    let column = parse_section("Column", 15)?;
+    let row_h = parse_section("Row", 4)?;
+
+    let id = (segment << 6) | (column << 2) | row_h;
+
+    let warning = if sections.next().is_some() {
+        // MAME matches the full title, so this segment could never be lit there
+        Some(format!(
+            "Title {title} contained too many groups. The extra groups were ignored"
+        ))
+    } else if row_h > 3 {
+        // Row is only 2 bits in the ID, so the high bit spills into the column
+        Some(format!(
+            "Row {row_h} in {title} was out of bounds. It was read as {}",
+            format_segment_id(id)
+        ))
+    } else {
+        None
+    };
+
+    Ok(ParsedTitle { id, warning })
+}
+
+/// A segment title that could not be used, or was used despite a problem
+#[derive(Clone, Debug)]
+pub struct TitleIssue {
+    /// The id of the path or group the title belongs to
+    pub element_id: Option<String>,
+    pub message: String,
+    /// Whether the title was ignored. Otherwise it was used as parsed
+    pub rejected: bool,
+}
+
+#[allow(clippy::diverging_sub_expression)]
+fn correlate_id_to_title(
+    contents: &str,
+) -> Result<(HashMap<String, u16>, Vec<TitleIssue>), String> {
     let mut svg_id_to_title: HashMap<String, u16> = HashMap::new();
+    let mut title_issues: Vec<TitleIssue> = vec![];
 
     #[derive(PartialEq, Debug)]
     struct ActiveGroup {
@@ -239,7 +370,7 @@ fn correlate_id_to_title(contents: &String) -> Result<HashMap<String, u16>, Stri
             svg::parser::Event::Tag("path", Type::Start, attributes) => {
                 let id: Option<String> = attributes.get("id").map(|v| v.clone().into());
 
-                if active_path != None {
+                if active_path.is_some() {
                     return Err(format!("SVG contains invalid nested paths at {id:?}"));
                 }
 
@@ -258,11 +389,38 @@ fn correlate_id_to_title(contents: &String) -> Result<HashMap<String, u16>, Stri
                     continue;
                 }
 
-                if let Some(ActivePath { ref mut title, .. }) = active_path {
-                    *title = parse_title(value);
-                } else if let Some(ActiveGroup { title, .. }) = group_stack.last_mut() {
+                let mut record_title = |element_id: &Option<String>| match parse_title(value) {
+                    Ok(ParsedTitle { id, warning }) => {
+                        if let Some(message) = warning {
+                            title_issues.push(TitleIssue {
+                                element_id: element_id.clone(),
+                                message,
+                                rejected: false,
+                            });
+                        }
+
+                        Some(id)
+                    }
+                    Err(message) => {
+                        title_issues.push(TitleIssue {
+                            element_id: element_id.clone(),
+                            message,
+                            rejected: true,
+                        });
+
+                        None
+                    }
+                };
+
+                if let Some(ActivePath {
+                    ref id,
+                    ref mut title,
+                }) = active_path
+                {
+                    *title = record_title(id);
+                } else if let Some(ActiveGroup { id, title, .. }) = group_stack.last_mut() {
                     // Set group title
-                    *title = parse_title(value);
+                    *title = record_title(id);
                 }
             }
             svg::parser::Event::Tag("title", Type::End, _) => {
@@ -328,22 +486,19 @@ fn correlate_id_to_title(contents: &String) -> Result<HashMap<String, u16>, Stri
         }
     }
 
-    Ok(svg_id_to_title)
+    Ok((svg_id_to_title, title_issues))
 }
 
 fn keep_usvg_node(node: &usvg::Node, svg_id_to_title: &HashMap<String, u16>) -> bool {
-    match *node.borrow() {
-        usvg::NodeKind::Path(ref path) => {
-            if path.id.is_empty() {
-                return false;
-            }
+    if let usvg::NodeKind::Path(ref path) = *node.borrow() {
+        if path.id.is_empty() {
+            return false;
+        }
 
-            if !svg_id_to_title.contains_key(&path.id) {
-                return false;
-            }
+        if !svg_id_to_title.contains_key(&path.id) {
+            return false;
         }
-        _ => {}
     }
 
-    return true;
+    true
 }
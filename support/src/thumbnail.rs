@@ -0,0 +1,80 @@
+use std::{fs, path::Path};
+
+use image::{
+    imageops::{self, FilterType},
+    GrayImage, Luma, RgbaImage,
+};
+use resvg::tiny_skia::Pixmap;
+
+/// The Pocket's library images are displayed at 521x165
+const POCKET_IMAGE_WIDTH: u32 = 521;
+const POCKET_IMAGE_HEIGHT: u32 = 165;
+
+///
+/// Downsize the rendered composite, with every segment lit, to fit the Pocket's library image height
+///
+pub fn build_thumbnail(composite: &Pixmap) -> RgbaImage {
+    let pixels = composite
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let pixel = pixel.demultiply();
+
+            [pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()]
+        })
+        .collect::<Vec<u8>>();
+
+    // Pixmaps are always the size they claim, so this can't fail
+    let image = RgbaImage::from_raw(composite.width(), composite.height(), pixels).unwrap();
+
+    let scale = POCKET_IMAGE_HEIGHT as f32 / image.height() as f32;
+    let width = ((image.width() as f32 * scale).round() as u32).min(POCKET_IMAGE_WIDTH);
+
+    imageops::resize(&image, width, POCKET_IMAGE_HEIGHT, FilterType::Lanczos3)
+}
+
+///
+/// Encode a thumbnail in the Pocket's image format. The thumbnail is converted to grayscale and centered on a black
+/// 521x165 canvas, which is stored rotated 90 degrees counterclockwise as 16 bit pixels, brightness first
+///
+pub fn encode_pocket_image(thumbnail: &RgbaImage) -> Vec<u8> {
+    let mut canvas = GrayImage::from_pixel(POCKET_IMAGE_WIDTH, POCKET_IMAGE_HEIGHT, Luma([0]));
+
+    let gray = imageops::grayscale(thumbnail);
+    let x = (POCKET_IMAGE_WIDTH - gray.width()) / 2;
+    let y = (POCKET_IMAGE_HEIGHT - gray.height()) / 2;
+
+    imageops::overlay(&mut canvas, &gray, x as i64, y as i64);
+
+    let rotated = imageops::rotate270(&canvas);
+
+    rotated
+        .pixels()
+        .flat_map(|Luma([brightness])| [*brightness, 0])
+        .collect()
+}
+
+///
+/// Write the game's thumbnail as a Pocket `.bin` image and a PNG
+///
+pub fn write_thumbnails(
+    composite: &Pixmap,
+    bin_path: &Path,
+    png_path: &Path,
+) -> Result<(), String> {
+    let thumbnail = build_thumbnail(composite);
+
+    for path in [bin_path, png_path] {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| format!("Could not create directory {parent:?}: {err}"))?;
+        }
+    }
+
+    fs::write(bin_path, encode_pocket_image(&thumbnail))
+        .map_err(|err| format!("Could not write {bin_path:?}: {err}"))?;
+
+    thumbnail
+        .save(png_path)
+        .map_err(|err| format!("Could not write {png_path:?}: {err}"))
+}
@@ -0,0 +1,199 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use colored::Colorize;
+use sha1::{Digest, Sha1};
+
+use crate::{
+    encode_format::{
+        generator_version, BYTES_PER_ENTRY, CONFIG_LENGTH, EXTENDED_MASK_ENTRIES, GIT_SHA,
+        IMAGE_LENGTH, MASK_FLAGS, MASK_FLAG_EXTENDED_TABLE, PROVENANCE,
+        PROVENANCE_GENERATOR_VERSION, PROVENANCE_MANIFEST_HASH, PROVENANCE_PAYLOAD_HASH,
+        PROVENANCE_ROM_HASH, PROVENANCE_VERSION, STANDARD_MASK_ENTRIES,
+    },
+    manifest::PlatformSpecification,
+};
+
+/// The parsed manifest, and the hash of each of its entries
+pub type ManifestEntries<'a> = (
+    &'a HashMap<String, PlatformSpecification>,
+    &'a HashMap<String, [u8; 20]>,
+);
+
+pub struct VerifyResult {
+    /// Problems that mean the file is damaged and shouldn't be used
+    pub errors: Vec<String>,
+    /// Problems that mean the file should be regenerated, but is intact
+    pub warnings: Vec<String>,
+}
+
+///
+/// Check a generated .gnw file against its recorded provenance, and against the current manifest if available
+///
+pub fn verify(path: &Path, manifest: Option<ManifestEntries>) -> Result<VerifyResult, String> {
+    let file = fs::read(path).map_err(|err| format!("Could not read {path:?}: {err}"))?;
+
+    let mut result = VerifyResult {
+        errors: vec![],
+        warnings: vec![],
+    };
+
+    let mask_start = CONFIG_LENGTH + IMAGE_LENGTH;
+
+    if file.len() < mask_start {
+        result.errors.push(format!(
+            "File is truncated. Expected at least {mask_start} bytes, found {}",
+            file.len()
+        ));
+        return Ok(result);
+    }
+
+    let git_sha = String::from_utf8_lossy(&file[GIT_SHA..CONFIG_LENGTH])
+        .trim_end_matches('\0')
+        .to_string();
+    let git_sha = if git_sha.is_empty() {
+        "unknown".to_string()
+    } else {
+        git_sha
+    };
+
+    if file[PROVENANCE] != PROVENANCE_VERSION {
+        result.warnings.push(format!(
+            "No provenance recorded. File was generated by an outdated generator (git {git_sha})"
+        ));
+        return Ok(result);
+    }
+
+    let read_hash = |offset: usize| -> [u8; 20] { file[offset..offset + 20].try_into().unwrap() };
+
+    let payload_hash = read_hash(PROVENANCE_PAYLOAD_HASH);
+    let rom_hash = read_hash(PROVENANCE_ROM_HASH);
+    let manifest_hash = read_hash(PROVENANCE_MANIFEST_HASH);
+    let version = &file[PROVENANCE_GENERATOR_VERSION..PROVENANCE_GENERATOR_VERSION + 3];
+
+    if Sha1::digest(&file[CONFIG_LENGTH..]).as_slice() != payload_hash {
+        result
+            .errors
+            .push("Payload checksum mismatch. The image, mask, or ROM data is corrupted".into());
+    }
+
+    let mask_entries = if file[MASK_FLAGS] & MASK_FLAG_EXTENDED_TABLE != 0 {
+        u32::from_le_bytes(
+            file[EXTENDED_MASK_ENTRIES..EXTENDED_MASK_ENTRIES + 4]
+                .try_into()
+                .unwrap(),
+        ) as usize
+    } else {
+        STANDARD_MASK_ENTRIES
+    };
+
+    let rom_start = mask_start + mask_entries * BYTES_PER_ENTRY;
+
+    if file.len() <= rom_start {
+        result
+            .errors
+            .push("File is truncated. No ROM data was found".into());
+    } else if Sha1::digest(&file[rom_start..]).as_slice() != rom_hash {
+        result
+            .errors
+            .push("ROM checksum mismatch. The embedded ROM is corrupted".into());
+    }
+
+    let current_version = generator_version();
+
+    if version < current_version.as_slice() {
+        result.warnings.push(format!(
+            "Generated by an outdated generator, version {}.{}.{} (git {git_sha}). Current version is {}.{}.{}",
+            version[0],
+            version[1],
+            version[2],
+            current_version[0],
+            current_version[1],
+            current_version[2]
+        ));
+    }
+
+    if let Some((manifest, manifest_hashes)) = manifest {
+        let rom_hash = hex::encode(rom_hash);
+
+        // Multiple entries can share a ROM, so any of them having the recorded hash is a match
+        let entries = manifest
+            .iter()
+            .filter(|(_, p)| {
+                let patched_hash = p.rom.patch.as_ref().and_then(|p| p.patched_hash.as_ref());
+
+                p.rom.rom_hash.to_lowercase() == rom_hash
+                    || patched_hash.is_some_and(|hash| hash.to_lowercase() == rom_hash)
+            })
+            .map(|(name, _)| name)
+            .collect::<Vec<&String>>();
+
+        if entries.is_empty() {
+            result.warnings.push(format!(
+                "No manifest entry uses the ROM with SHA-1 {rom_hash}"
+            ));
+        } else if !entries
+            .iter()
+            .any(|name| manifest_hashes.get(*name) == Some(&manifest_hash))
+        {
+            let mut names = entries
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<&str>>();
+            names.sort();
+
+            result.warnings.push(format!(
+                "Manifest entry has changed since generation ({}). Regenerate to pick up the changes",
+                names.join(", ")
+            ));
+        }
+    }
+
+    Ok(result)
+}
+
+///
+/// Verify each file, printing the results. Returns false if any file failed
+///
+pub fn verify_files(paths: &[impl AsRef<Path>], manifest: Option<ManifestEntries>) -> bool {
+    let mut ok_count = 0;
+    let mut warning_count = 0;
+    let mut fail_count = 0;
+
+    for path in paths {
+        let path = path.as_ref();
+
+        let result = match verify(path, manifest) {
+            Ok(result) => result,
+            Err(err) => {
+                println!("{}", err.red());
+                fail_count += 1;
+                continue;
+            }
+        };
+
+        for error in &result.errors {
+            println!("{}: {}", path.display(), error.red());
+        }
+
+        for warning in &result.warnings {
+            println!("{}: {}", path.display(), warning.yellow());
+        }
+
+        if !result.errors.is_empty() {
+            fail_count += 1;
+        } else if !result.warnings.is_empty() {
+            warning_count += 1;
+        } else {
+            println!("{}: {}", path.display(), "OK".green());
+            ok_count += 1;
+        }
+    }
+
+    println!("-------------------------");
+    println!(
+        "Total: {}, OK: {ok_count}, Outdated: {warning_count}, Fail: {fail_count}",
+        paths.len()
+    );
+
+    fail_count == 0
+}
@@ -0,0 +1,416 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use colored::Colorize;
+use sha1::{Digest, Sha1};
+
+use crate::{
+    assets::{get_assets, load_rom, AssetPaths},
+    encode_format::{encode, EncodeOptions},
+    layout::{parse_layout, MameLayout, View},
+    manifest::PlatformSpecification,
+    naming::OutputNaming,
+    patch::patch_rom,
+    project::{load_project, project_dir, Project, PROJECT_DESCRIPTOR},
+    render::{render_cached, RenderOptions, RenderedData, SvgCache},
+    segment_report::SegmentReport,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+///
+/// The stages of a build, in order. Rerunning a stage also reruns every later stage
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Stage {
+    /// Parse the layout, or reload the project descriptor
+    Layout,
+    /// Map the screen SVGs' segment IDs to pixels
+    Svg,
+    /// Composite the backgrounds and screens
+    Composite,
+    /// Load the ROM and write the .gnw
+    Encode,
+}
+
+pub enum WatchTarget<'a> {
+    Game {
+        name: &'a str,
+        platform: &'a PlatformSpecification,
+        manifest_hash: [u8; 20],
+        paths: &'a AssetPaths,
+        asset_dir: PathBuf,
+        layout_name: Option<&'a String>,
+        output_path: PathBuf,
+        allow_rom_mismatch: bool,
+        allow_patched_rom_mismatch: bool,
+    },
+    Project {
+        path: PathBuf,
+        output_dir: PathBuf,
+        naming: &'a OutputNaming,
+    },
+}
+
+///
+/// The results of the last successful build, reused by later builds that don't need to rerun those stages
+///
+#[derive(Default)]
+struct WatchState {
+    project: Option<Project>,
+    layout: Option<(MameLayout, View)>,
+    svg_cache: SvgCache,
+    rendered: Option<RenderedData>,
+    previous_report: Option<SegmentReport>,
+}
+
+impl<'a> WatchTarget<'a> {
+    ///
+    /// The sets or folders that are watched for changes
+    ///
+    fn sources(&self) -> Vec<PathBuf> {
+        match self {
+            WatchTarget::Game {
+                name,
+                platform,
+                paths,
+                ..
+            } => [
+                paths.find_artwork_set(name),
+                paths.find_rom_set(name),
+                platform
+                    .rom
+                    .rom_owner
+                    .as_ref()
+                    .and_then(|owner| paths.find_rom_set(owner)),
+            ]
+            .into_iter()
+            .flatten()
+            .map(|source| source.path().to_path_buf())
+            .collect(),
+            WatchTarget::Project { path, .. } => vec![project_dir(path)],
+        }
+    }
+
+    ///
+    /// The directory the build reads its files from
+    ///
+    fn asset_dir(&self) -> PathBuf {
+        match self {
+            WatchTarget::Game { asset_dir, .. } => asset_dir.clone(),
+            WatchTarget::Project { path, .. } => project_dir(path),
+        }
+    }
+
+    ///
+    /// Bring the asset directory up to date with the sources
+    ///
+    fn refresh(&self) -> Result<(), String> {
+        match self {
+            WatchTarget::Game {
+                name,
+                platform,
+                paths,
+                asset_dir,
+                ..
+            } => get_assets(name, &platform.rom.rom_owner, paths, asset_dir),
+            // Projects are built in place
+            WatchTarget::Project { .. } => Ok(()),
+        }
+    }
+
+    ///
+    /// Files written by the build, which must not trigger another build
+    ///
+    fn is_output(&self, path: &Path, state: &WatchState) -> bool {
+        let key = match (self, &state.project) {
+            (WatchTarget::Game { name, .. }, _) => name.to_string(),
+            (WatchTarget::Project { .. }, Some(project)) => project.key.clone(),
+            (WatchTarget::Project { .. }, None) => String::new(),
+        };
+
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        path.extension().is_some_and(|ext| ext == "gnw")
+            || [
+                format!("{key}.png"),
+                format!("{key}_background.png"),
+                format!("{key}_mask.png"),
+            ]
+            .contains(&file_name)
+    }
+
+    ///
+    /// The earliest stage affected by a changed file, if any
+    ///
+    fn stage_for(&self, path: &Path, state: &WatchState) -> Option<Stage> {
+        let file_name = path.file_name()?.to_string_lossy().to_lowercase();
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+
+        let rom_name = match (self, &state.project) {
+            (WatchTarget::Game { platform, .. }, _) => Some(&platform.rom.rom),
+            (WatchTarget::Project { .. }, Some(project)) => Some(&project.platform.rom.rom),
+            (WatchTarget::Project { .. }, None) => None,
+        };
+
+        if rom_name.is_some_and(|rom| rom.to_lowercase() == file_name) {
+            return Some(Stage::Encode);
+        }
+
+        match extension.as_str() {
+            "lay" => Some(Stage::Layout),
+            _ if file_name == PROJECT_DESCRIPTOR => Some(Stage::Layout),
+            "svg" => Some(Stage::Svg),
+            "png" => Some(Stage::Composite),
+            _ => None,
+        }
+    }
+
+    ///
+    /// Rerun the build from `stage`. Returns the path of the written .gnw
+    ///
+    fn build(
+        &self,
+        stage: Stage,
+        state: &mut WatchState,
+        render_options: &RenderOptions,
+        encode_options: &EncodeOptions,
+    ) -> Result<PathBuf, String> {
+        let WatchState {
+            project,
+            layout,
+            svg_cache,
+            rendered,
+            previous_report,
+        } = state;
+
+        if stage <= Stage::Layout {
+            match self {
+                WatchTarget::Game {
+                    asset_dir,
+                    layout_name,
+                    ..
+                } => *layout = Some(parse_layout(asset_dir, *layout_name)?),
+                WatchTarget::Project { path, .. } => *project = Some(load_project(path)?),
+            }
+        }
+
+        if stage <= Stage::Svg {
+            svg_cache.clear();
+        }
+
+        let (name, platform, layout_manifest, view) = match (self, &*project, &*layout) {
+            (WatchTarget::Game { name, platform, .. }, _, Some((layout_manifest, view))) => {
+                (name.to_string(), *platform, layout_manifest, view)
+            }
+            (WatchTarget::Project { .. }, Some(project), _) => (
+                project.key.clone(),
+                &project.platform,
+                &project.layout_manifest,
+                &project.view,
+            ),
+            _ => return Err("Nothing has been loaded to build".to_string()),
+        };
+
+        if stage <= Stage::Composite || rendered.is_none() {
+            let data = render_cached(
+                &name,
+                view,
+                layout_manifest,
+                platform,
+                &self.asset_dir(),
+                render_options,
+                svg_cache,
+            )?;
+
+            if let Some(previous_report) = previous_report {
+                data.segment_report.print_diff(previous_report);
+            }
+
+            *previous_report = Some(data.segment_report.clone());
+            *rendered = Some(data);
+        }
+
+        // Always set by the render above
+        let rendered_data = rendered.as_ref().unwrap();
+
+        let (rom_data, manifest_hash, output_path) = match (self, &*project) {
+            (
+                WatchTarget::Game {
+                    manifest_hash,
+                    paths,
+                    asset_dir,
+                    output_path,
+                    allow_rom_mismatch,
+                    allow_patched_rom_mismatch,
+                    ..
+                },
+                _,
+            ) => {
+                let rom_data =
+                    load_rom(&name, &platform.rom, paths, asset_dir, *allow_rom_mismatch)?;
+
+                let rom_data = match &platform.rom.patch {
+                    Some(patch) => patch_rom(&rom_data, patch, *allow_patched_rom_mismatch)?,
+                    None => rom_data,
+                };
+
+                (rom_data, *manifest_hash, output_path.clone())
+            }
+            (
+                WatchTarget::Project {
+                    output_dir, naming, ..
+                },
+                Some(project),
+            ) => (
+                project.load_rom()?,
+                project.descriptor_hash,
+                project.output_path(output_dir, naming),
+            ),
+            (WatchTarget::Project { .. }, None) => unreachable!(),
+        };
+
+        encode(
+            &name,
+            rendered_data,
+            platform,
+            &manifest_hash,
+            rom_data,
+            &output_path,
+            encode_options,
+        )?;
+
+        Ok(output_path)
+    }
+}
+
+fn list_files(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_file() {
+        files.push(path.to_path_buf());
+        return;
+    }
+
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            list_files(&entry.path(), files);
+        }
+    }
+}
+
+///
+/// Rebuild a single game or project whenever its source files change, until the process is stopped. Sources are
+/// polled, and only the stages affected by the changed files are rerun
+///
+#[allow(clippy::diverging_sub_expression)]
+pub fn watch(target: &WatchTarget, render_options: &RenderOptions, encode_options: &EncodeOptions) {
+    let mut state = WatchState::default();
+    let mut source_times: Option<BTreeMap<PathBuf, SystemTime>> = None;
+    let mut asset_hashes: BTreeMap<PathBuf, [u8; 20]> = BTreeMap::new();
+
+    println!("Watching for changes. Press Ctrl+C to stop\n");
+
+    loop {
+        let mut sources = vec![];
+
+        for source in target.sources() {
+            list_files(&source, &mut sources);
+        }
+
+        let times = sources
+            .into_iter()
+            .filter(|path| !target.is_output(path, &state))
+            .filter_map(|path| {
+                let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+
+                Some((path, modified))
+            })
+            .collect::<BTreeMap<PathBuf, SystemTime>>();
+
+        if source_times.as_ref() == Some(&times) {
+            thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+
+        source_times = Some(times);
+
+        if let Err(err) = target.refresh() {
+            println!("{}", err.red());
+            continue;
+        }
+
+        let mut files = vec![];
+        list_files(&target.asset_dir(), &mut files);
+
+        let hashes = files
+            .into_iter()
+            .filter(|path| !target.is_output(path, &state))
+            .filter_map(|path| {
+                let hash = Sha1::digest(fs::read(&path).ok()?).into();
+
+                Some((path, hash))
+            })
+            .collect::<BTreeMap<PathBuf, [u8; 20]>>();
+
+        // Added, removed, and modified files
+        let changed = hashes
+            .iter()
+            .filter(|(path, hash)| asset_hashes.get(*path) != Some(*hash))
+            .map(|(path, _)| path)
+            .chain(
+                asset_hashes
+                    .keys()
+                    .filter(|path| !hashes.contains_key(*path)),
+            )
+            .cloned()
+            .collect::<BTreeSet<PathBuf>>();
+
+        asset_hashes = hashes;
+
+        // Nothing has been built yet, or the last build failed
+        let full_build = state.rendered.is_none();
+
+        let stage = if full_build {
+            Some(Stage::Layout)
+        } else {
+            changed
+                .iter()
+                .filter_map(|path| target.stage_for(path, &state))
+                .min()
+        };
+
+        guard!(let Some(stage) = stage else {
+            continue;
+        });
+
+        println!("-------------------------");
+
+        if !full_build {
+            for path in &changed {
+                println!("Changed {}", path.display());
+            }
+        }
+
+        println!("Rebuilding from the {stage:?} stage\n");
+
+        match target.build(stage, &mut state, render_options, encode_options) {
+            Ok(output_path) => {
+                println!("{}", format!("Rebuilt {}\n", output_path.display()).green())
+            }
+            Err(err) => {
+                println!("{}", err.red());
+                println!("{}", "Waiting for changes\n".red());
+
+                // Start from scratch on the next change
+                state.rendered = None;
+            }
+        }
+    }
+}